@@ -0,0 +1,30 @@
+//! Runs the `commonmark` preset against CommonMark spec examples and prints
+//! pass/fail counts by section.
+//!
+//! Usage: `cargo run --example commonmark_spec [path/to/spec.json]`, defaulting
+//! to the copy of the spec's examples in `tests/fixtures`. Pass `--failures` to
+//! print failed examples.
+
+use simple_markdown_rs::{commonmark_to_html, parse_spec_examples, run_spec_examples};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map(String::as_str)
+        .unwrap_or("tests/fixtures/commonmark_spec.json");
+    let json = std::fs::read_to_string(path).expect("failed to read spec examples");
+    let examples = parse_spec_examples(&json).expect("invalid spec examples");
+    let report = run_spec_examples(&examples, commonmark_to_html);
+
+    if args.iter().any(|arg| arg == "--failures") {
+        for (example, html) in &report.failures {
+            let number = example.example.map_or("?".to_string(), |n| n.to_string());
+            println!("--- {} #{} {:?}", example.section, number, example.markdown);
+            println!("expected: {:?}", example.html);
+            println!("actual:   {:?}", html);
+        }
+    }
+    println!("{}", report);
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use fancy_regex::Regex;
+use serde_json::Value;
+
+use crate::default_rules::{create_default_rules, RuleMap};
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, register_outputs, task_marker, OutputFunction,
+};
+use crate::parse::parser_for;
+
+lazy_static::lazy_static! {
+    static ref BETWEEN_TAGS_R: Regex = Regex::new(r">\n+<").unwrap();
+    static ref HEADING_R: Regex =
+        Regex::new(r"^( {0,3}(#{1,6})(?: +([^\n]*?))?(?: +#+)? *)(?:\n|$)").unwrap();
+    static ref LHEADING_R: Regex = Regex::new(r"^(([^\n]+)\n {0,3}(=|-)+ *)(?:\n|$)").unwrap();
+    static ref STRONG_R: Regex = Regex::new(r"^(\*\*|__)((?:\\[\s\S]|[^\\])+?)\1(?![*_])").unwrap();
+    static ref BR_R: Regex = Regex::new(r"^(?: {2,}|\\)\n").unwrap();
+}
+
+/// Escapes text the way the CommonMark reference renderer does.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn attributes(attributes: &[(&str, &str)]) -> String {
+    attributes
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_html(value)))
+        .collect()
+}
+
+/// Output functions for the `commonmark` format, matching the HTML of the
+/// CommonMark reference renderer.
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let level = node_value(node, "level");
+            let content = output_inline(nested, node_value(node, "content"), state);
+            format!("<h{}>{}</h{}>\n", level, content.trim(), level)
+        }),
+        ("hr", |_node, _nested, _state| "<hr />\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            let content = node_value(node, "content");
+            let lang = node_value(node, "lang");
+            let class_name = if lang.is_empty() {
+                String::new()
+            } else {
+                format!("language-{}", lang)
+            };
+            let newline = if content.is_empty() { "" } else { "\n" };
+            format!(
+                "<pre><code{}>{}{}</code></pre>\n",
+                attributes(&[("class", &class_name)]),
+                escape_html(content),
+                newline
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            format!("<blockquote>\n{}</blockquote>\n", content)
+        }),
+        ("list", |node, nested, state| {
+            // CommonMark has no task lists, so their markers stay text
+            let items: String = list_items(node)
+                .iter()
+                .zip(list_checked(node))
+                .map(|(item, checked)| {
+                    let item = format!("{}{}", task_marker(checked, "[ ] ", "[x] "), item);
+                    let content = output_list_item(node, nested, &item, state);
+                    if item.contains("\n\n") {
+                        format!("<li>\n{}</li>\n", content)
+                    } else {
+                        format!("<li>{}</li>\n", content.trim_end())
+                    }
+                })
+                .collect();
+            let start = node_value(node, "start");
+            if node_value(node, "ordered") == "true" {
+                let start = if start == "1" { "" } else { start };
+                format!("<ol{}>\n{}</ol>\n", attributes(&[("start", start)]), items)
+            } else {
+                format!("<ul>\n{}</ul>\n", items)
+            }
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("newline", |_node, _nested, _state| String::new()),
+        ("paragraph", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            format!("<p>{}</p>\n", content.trim())
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            let title = node_value(node, "title");
+            format!(
+                "<a href=\"{}\"{}>{}</a>",
+                escape_html(node_value(node, "target")),
+                attributes(&[("title", title)]),
+                content
+            )
+        }),
+        ("image", |node, _nested, _state| {
+            format!(
+                "<img src=\"{}\" alt=\"{}\"{} />",
+                escape_html(node_value(node, "target")),
+                escape_html(node_value(node, "alt")),
+                attributes(&[("title", node_value(node, "title"))])
+            )
+        }),
+        ("em", |node, nested, state| {
+            format!(
+                "<em>{}</em>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("strong", |node, nested, state| {
+            format!(
+                "<strong>{}</strong>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            format!("<code>{}</code>", escape_html(node_value(node, "content")))
+        }),
+        ("br", |_node, _nested, _state| "<br />\n".to_string()),
+        ("text", |node, _nested, _state| {
+            escape_html(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Creates a rule preset closer to CommonMark than [`create_default_rules`].
+///
+/// Extensions CommonMark doesn't have (`u`, `del`, bare `url` autolinks,
+/// tables, footnotes, spoilers, mentions and custom emoji) are removed, `__`
+/// is strong emphasis and a backslash before a newline is a hard line break.
+/// Block structure still follows the default rules, so e.g. lists can't
+/// interrupt paragraphs. Its rules output the `commonmark` format.
+pub fn create_commonmark_rules() -> RuleMap {
+    let mut rules = create_default_rules();
+    for rule_type in [
+        "u",
+        "del",
+        "url",
+        "table",
+        "nptable",
+        "footnoteRef",
+        "footnoteDef",
+        "footnotes",
+        "spoiler",
+        "mention",
+        "emoji",
+    ] {
+        rules.remove(rule_type);
+    }
+
+    // ATX headings need a space after the `#`s and don't need a blank line after them
+    if let Some(heading) = rules.get_mut("heading") {
+        heading.match_fn = Some(|source, _state| {
+            HEADING_R.captures(source).ok().flatten().map(|caps| {
+                vec![
+                    caps[1].to_string(),
+                    caps[2].to_string(),
+                    caps.get(3).map_or("", |m| m.as_str()).to_string(),
+                ]
+            })
+        });
+    }
+    if let Some(lheading) = rules.get_mut("lheading") {
+        lheading.match_fn = Some(|source, _state| {
+            LHEADING_R.captures(source).ok().flatten().map(|caps| {
+                vec![
+                    caps[1].to_string(),
+                    caps[2].trim().to_string(),
+                    caps[3].to_string(),
+                ]
+            })
+        });
+        lheading.parse = Some(|capture, _state| {
+            HashMap::from([
+                ("type".to_string(), "heading".to_string()),
+                (
+                    "level".to_string(),
+                    if capture[2] == "=" { "1" } else { "2" }.to_string(),
+                ),
+                ("content".to_string(), capture[1].clone()),
+            ])
+        });
+    }
+
+    // Paragraphs are tried before inline rules, as upstream, and lose the
+    // indentation of their lines
+    let newline_order = rules.get("newline").and_then(|newline| newline.order);
+    if let Some(paragraph) = rules.get_mut("paragraph") {
+        paragraph.order = newline_order.map(|order| order + 0.5);
+        paragraph.parse = Some(|capture, _state| {
+            let content: Vec<&str> = capture[0].lines().map(str::trim_start).collect();
+            HashMap::from([
+                ("type".to_string(), "paragraph".to_string()),
+                ("content".to_string(), content.join("\n")),
+            ])
+        });
+    }
+
+    // Code spans turn line endings into spaces and strip one space from each side
+    if let Some(inline_code) = rules.get_mut("inlineCode") {
+        inline_code.parse = Some(|capture, _state| {
+            let content = capture[1].replace('\n', " ");
+            let content = if content.len() > 2
+                && content.starts_with(' ')
+                && content.ends_with(' ')
+                && !content.trim().is_empty()
+            {
+                content[1..content.len() - 1].to_string()
+            } else {
+                content
+            };
+            HashMap::from([
+                ("type".to_string(), "inlineCode".to_string()),
+                ("content".to_string(), content),
+            ])
+        });
+    }
+
+    if let Some(strong) = rules.get_mut("strong") {
+        strong.match_fn = Some(|source, _state| {
+            STRONG_R
+                .captures(source)
+                .ok()
+                .flatten()
+                .map(|caps| vec![caps[0].to_string(), caps[2].to_string()])
+        });
+    }
+    if let Some(br) = rules.get_mut("br") {
+        br.match_fn = Some(|source, _state| {
+            BR_R.captures(source)
+                .ok()
+                .flatten()
+                .map(|caps| vec![caps[0].to_string()])
+        });
+    }
+
+    register_outputs(&mut rules, "commonmark", outputs());
+    rules
+}
+
+/// Converts `source` to HTML with the [`create_commonmark_rules`] preset.
+pub fn commonmark_to_html(source: &str) -> String {
+    let rules = create_commonmark_rules();
+    let parse = parser_for(rules.clone());
+    // Block rules expect to be terminated by a blank line, as in upstream's block parse
+    let ast = parse(&format!("{}\n\n", source.trim_end_matches('\n')), None);
+    output_for(rules, "commonmark")(&ast, None)
+}
+
+/// One example of the CommonMark spec, as found in its `spec.json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecExample {
+    pub markdown: String,
+    pub html: String,
+    pub section: String,
+    /// Number of the example in the spec, if known.
+    pub example: Option<u64>,
+}
+
+/// Parses examples in the format of the CommonMark `spec.json`.
+pub fn parse_spec_examples(json: &str) -> Result<Vec<SpecExample>, serde_json::Error> {
+    let examples: Vec<Value> = serde_json::from_str(json)?;
+    Ok(examples
+        .iter()
+        .map(|example| SpecExample {
+            markdown: example["markdown"].as_str().unwrap_or("").to_string(),
+            html: example["html"].as_str().unwrap_or("").to_string(),
+            section: example["section"].as_str().unwrap_or("").to_string(),
+            example: example["example"].as_u64(),
+        })
+        .collect())
+}
+
+/// Pass and fail counts of one spec section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecSection {
+    pub name: String,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Result of running spec examples, by section in spec order.
+#[derive(Debug, Clone, Default)]
+pub struct SpecReport {
+    pub sections: Vec<SpecSection>,
+    /// Failed examples, with the HTML that was rendered for each.
+    pub failures: Vec<(SpecExample, String)>,
+}
+
+impl SpecReport {
+    pub fn passed(&self) -> usize {
+        self.sections.iter().map(|section| section.passed).sum()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.sections.iter().map(|section| section.failed).sum()
+    }
+}
+
+impl fmt::Display for SpecReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for section in &self.sections {
+            writeln!(
+                f,
+                "{:<32} {:>4} passed {:>4} failed",
+                section.name, section.passed, section.failed
+            )?;
+        }
+        write!(
+            f,
+            "{:<32} {:>4} passed {:>4} failed",
+            "Total",
+            self.passed(),
+            self.failed()
+        )
+    }
+}
+
+/// Removes the newlines between tags, which the spec doesn't test for.
+fn normalize_html(html: &str) -> String {
+    BETWEEN_TAGS_R.replace_all(html.trim(), "><").to_string()
+}
+
+/// Renders each example with `render` and compares it to the expected HTML.
+pub fn run_spec_examples(examples: &[SpecExample], render: impl Fn(&str) -> String) -> SpecReport {
+    let mut report = SpecReport::default();
+    for example in examples {
+        let html = render(&example.markdown);
+        let passed = normalize_html(&html) == normalize_html(&example.html);
+
+        if report.sections.last().map(|section| &section.name) != Some(&example.section) {
+            report.sections.push(SpecSection {
+                name: example.section.clone(),
+                passed: 0,
+                failed: 0,
+            });
+        }
+        let section = report.sections.last_mut().unwrap();
+        if passed {
+            section.passed += 1;
+        } else {
+            section.failed += 1;
+            report.failures.push((example.clone(), html));
+        }
+    }
+    report
+}
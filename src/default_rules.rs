@@ -1,781 +1,1278 @@
-use fancy_regex::Regex;
-use std::collections::HashMap;
-
-fn unescape_url(raw_url: &str) -> String {
-    let re = Regex::new(r"\\([^\d\sA-Za-z])").unwrap();
-    re.replace_all(raw_url, "$1").to_string()
-}
-
-lazy_static::lazy_static! {
-    static ref LIST_BULLET: &'static str = r"(?:[*+-]|\d+\.)";
-    static ref LIST_ITEM_PREFIX: &'static str = r"( *)(?:[*+-]|\d+\.) +";
-    static ref LIST_ITEM_PREFIX_R: Regex = Regex::new(&format!(r"^{}", *LIST_ITEM_PREFIX)).unwrap();
-    static ref LIST_ITEM_R: Regex = Regex::new(&format!(
-        r"^{}[^\n]*(?:\n(?!{} )[^\n]*)*(\n|$)",
-        *LIST_ITEM_PREFIX,
-        *LIST_BULLET
-    )).unwrap();
-    static ref BLOCK_END_R: Regex = Regex::new(r"\n{2,}$").unwrap();
-    static ref LIST_BLOCK_END_R: Regex = Regex::new(r"\n{2,}$").unwrap();
-    static ref LIST_ITEM_END_R: Regex = Regex::new(r" *\n+$").unwrap();
-    static ref LIST_R: Regex = Regex::new(&format!(
-        r"^( *)({})[^\S\n][\s\S]+?(?:\n{{2,}}(?! )(?!\1{} )\n*|\s*\n*$)",
-        *LIST_BULLET,
-        *LIST_BULLET
-    )).unwrap();
-    static ref LIST_LOOKBEHIND_R: Regex = Regex::new(r"(?:^|\n)( *)$").unwrap();
-    static ref INLINE_CODE_ESCAPE_BACKTICKS_R: Regex = Regex::new(r"^ (?= *`)|(` *) $").unwrap();
-    static ref LINK_INSIDE: &'static str = r#"(?:\[[^\]]*\]|[^\[\]]|\](?=[^\[]*\]))*"#;
-    static ref LINK_HREF_AND_TITLE: &'static str = r#"(?:\s*<(?:[^<>]|\\.)*>|\s*(?:[^\s\\\)]|\\.)*?)(?:\s+["']([^"']*)["'])?\s*"#;
-    static ref AUTOLINK_MAILTO_CHECK_R: Regex = Regex::new("(?i)mailto:").unwrap();
-}
-
-pub type RuleMap = HashMap<String, Rule>;
-pub type ASTNode = HashMap<String, String>;
-pub type ParserClosure = Box<dyn Fn(&str, Option<State>) -> Vec<ASTNode>>;
-pub type MatchFunction = fn(&str, &mut State) -> Option<Vec<String>>;
-pub type ParseFunction = fn(Vec<String>, &mut State) -> ASTNode;
-pub type CaptureLengthFunction = fn(&Vec<String>) -> usize;
-
-#[derive(Debug)]
-pub struct Rule {
-    pub order: Option<f64>,
-    pub match_fn: Option<MatchFunction>,
-    pub parse: Option<ParseFunction>,
-    pub capture_len: Option<CaptureLengthFunction>,
-}
-
-// Update State type to include boolean flags and refs
-#[derive(Default, Clone)]
-pub struct State {
-    pub inline: bool,
-    pub _list: bool,
-    pub prev_capture: Option<String>,
-    pub _refs: HashMap<String, Vec<ASTNode>>,
-    pub _defs: HashMap<String, ASTNode>,
-    pub data: HashMap<String, String>,
-}
-
-pub fn create_default_rules() -> HashMap<String, Rule> {
-    let mut rules = HashMap::new();
-    let mut curr_order = 0.0;
-
-    rules.insert(
-        "heading".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^ *(#{1,6})([^\n]+?)#* *(?:\n *)+\n").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string(), caps[2].trim().to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "heading".to_string()),
-                    ("level".to_string(), capture[0].len().to_string()),
-                    ("content".to_string(), capture[1].trim().to_string()),
-                ])
-            }),
-            capture_len: Some(|capture| 2 + capture[1].len()),
-        },
-    );
-
-    rules.insert(
-        "lheading".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^([^\n]+)\n *(=|-){3,} *(?:\n *)+\n").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string(), caps[2].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "heading".to_string()),
-                    (
-                        "level".to_string(),
-                        if capture[1] == "=" { "1" } else { "2" }.to_string(),
-                    ),
-                    ("content".to_string(), capture[0].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "hr".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^( *[*_-]){3,} *(?:\n *)+\n").unwrap();
-                regex.captures(source).ok().flatten().map(|_| vec![])
-            }),
-            parse: Some(|_capture, _state| HashMap::from([("type".to_string(), "hr".to_string())])),
-            capture_len: Some(|_| 1),
-        },
-    );
-
-    rules.insert(
-        "codeBlock".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^(?: {4}[^\n]+\n*)+(?:\n *)+\n").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                let content = capture[0]
-                    .replace(r"^ {4}", "")
-                    .trim_end_matches('\n')
-                    .to_string();
-                HashMap::from([
-                    ("type".to_string(), "codeBlock".to_string()),
-                    ("content".to_string(), content),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "fence".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex =
-                    Regex::new(r"^ *(`{3,}|~{3,}) *(?:(\S+) *)?\n([\S\s]+?)\n?\1 *(?:\n *)+\n")
-                        .unwrap();
-                regex.captures(source).ok().flatten().map(|caps| {
-                    vec![
-                        caps[1].to_string(),
-                        caps[2].to_string(),
-                        caps[3].to_string(),
-                    ]
-                })
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "codeBlock".to_string()),
-                    ("lang".to_string(), capture[1].clone()),
-                    ("content".to_string(), capture[2].clone()),
-                ])
-            }),
-            capture_len: Some(|capture| 3 + capture[0].len() + capture[1].len() + capture[2].len()),
-        },
-    );
-
-    rules.insert(
-        "blockQuote".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^( *>[^\n]+(\n[^\n]+)*\n*)+\n{2,}").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                let content = capture[0].replace(r"^ *> ?", "");
-                HashMap::from([
-                    ("type".to_string(), "blockQuote".to_string()),
-                    ("content".to_string(), content),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "list".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, state| {
-                let prev_capture = state.prev_capture.as_deref().unwrap_or("").to_string();
-
-                if let Ok(Some(lookbehind)) = LIST_LOOKBEHIND_R.captures(&prev_capture) {
-                    if state._list || !state.inline {
-                        let updated_source =
-                            format!("{}{}", lookbehind.get(1).map_or("", |m| m.as_str()), source);
-                        return LIST_R.captures(&updated_source).ok().flatten().map(|caps| {
-                            vec![
-                                caps[0].to_string(),
-                                caps[1].to_string(),
-                                caps[2].to_string(),
-                            ]
-                        });
-                    }
-                }
-                None
-            }),
-            parse: Some(|capture, _state| {
-                let bullet = &capture[2];
-                let ordered = bullet.len() > 1;
-                let start: Option<String> = if ordered {
-                    bullet.trim_end_matches('.').parse().ok()
-                } else {
-                    None
-                };
-
-                let items_str = LIST_BLOCK_END_R.replace(&capture[0], "\n");
-                let mut items = Vec::new();
-
-                // Fix the list item matching to handle fancy_regex::Matches correctly
-                let regex_matches = LIST_ITEM_R.find_iter(&items_str);
-                for m in regex_matches.flatten() {
-                    items.push(m.as_str().to_string());
-                }
-
-                HashMap::from([
-                    ("type".to_string(), "list".to_string()),
-                    ("ordered".to_string(), ordered.to_string()),
-                    (
-                        "start".to_string(),
-                        start.map_or("".to_string(), |n| n.to_string()),
-                    ),
-                    ("items".to_string(), serde_json::to_string(&items).unwrap()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "def".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(
-                    r#"^ *\[([^\]]+)]: *<?([^\s>]*)>?(?: +["(]([^\n]+)[")])?(?:\n *)+$"#,
-                )
-                .unwrap();
-                regex.captures(source).ok().flatten().map(|caps| {
-                    vec![
-                        caps[1].to_string(),
-                        caps[2].to_string(),
-                        caps.get(3)
-                            .map_or("".to_string(), |m| m.as_str().to_string()),
-                    ]
-                })
-            }),
-            parse: Some(|capture, _state| {
-                let def = capture[0].replace(r"\s+", " ").to_lowercase();
-                let target = capture[1].clone();
-                let title = capture[2].clone();
-
-                HashMap::from([
-                    ("type".to_string(), "def".to_string()),
-                    ("def".to_string(), def),
-                    ("target".to_string(), target),
-                    ("title".to_string(), title),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "newline".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^(?:\n *)*\n").unwrap();
-                regex.captures(source).ok().flatten().map(|_| vec![])
-            }),
-            parse: Some(|_capture, _state| {
-                HashMap::from([("type".to_string(), "newline".to_string())])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "escape".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^\\([^\d\sA-Za-z])").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "text".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "autolink".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^<([^ :>]+:\/[^ >]+)>").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "link".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                    ("target".to_string(), capture[0].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "mailto".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^<([^ >]+@[^ >]+)>").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                let address = capture[0].clone();
-                let target = if !AUTOLINK_MAILTO_CHECK_R.is_match(&address).unwrap_or(false) {
-                    format!("mailto:{}", address)
-                } else {
-                    address.clone()
-                };
-                HashMap::from([
-                    ("type".to_string(), "link".to_string()),
-                    ("content".to_string(), address),
-                    ("target".to_string(), target),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "url".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r#"^(https?:\/\/[^\s<]+[^:\s<"'\)\]}])"#).unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "link".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                    ("target".to_string(), capture[0].clone()),
-                    ("inline".to_string(), "true".to_string()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    // Replace the link rule implementation
-    rules.insert(
-        "link".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(&format!(
-                    r#"^\[({})]\(({})\)"#,
-                    *LINK_INSIDE, *LINK_HREF_AND_TITLE
-                ))
-                .unwrap();
-                regex.captures(source).ok().flatten().map(|caps| {
-                    vec![
-                        caps[0].to_string(),
-                        caps[1].to_string(),
-                        caps[2].to_string(),
-                        caps.get(3)
-                            .map_or("".to_string(), |m| m.as_str().to_string()),
-                    ]
-                })
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "link".to_string()),
-                    ("content".to_string(), capture[1].clone()),
-                    ("target".to_string(), unescape_url(&capture[2])),
-                    ("title".to_string(), capture[3].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "image".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(&format!(
-                    r#"^!\[({})]\(({})\)"#,
-                    *LINK_INSIDE, *LINK_HREF_AND_TITLE
-                ))
-                .unwrap();
-                regex.captures(source).ok().flatten().map(|caps| {
-                    vec![
-                        caps[0].to_string(),
-                        caps[1].to_string(),
-                        caps[2].to_string(),
-                        caps.get(3)
-                            .map_or("".to_string(), |m| m.as_str().to_string()),
-                    ]
-                })
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "image".to_string()),
-                    ("alt".to_string(), capture[1].clone()),
-                    ("target".to_string(), unescape_url(&capture[2])),
-                    ("title".to_string(), capture[3].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "reflink".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(&format!(r"^\[({})]\s*\[([^\]]*)]", *LINK_INSIDE)).unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string(), caps[2].to_string()])
-            }),
-            parse: Some(|capture, state| {
-                if let Some(def) = state._defs.get(&capture[1].to_lowercase()) {
-                    HashMap::from([
-                        ("type".to_string(), "link".to_string()),
-                        ("content".to_string(), capture[0].clone()),
-                        ("target".to_string(), def["target"].clone()),
-                        (
-                            "title".to_string(),
-                            def.get("title").cloned().unwrap_or_default(),
-                        ),
-                    ])
-                } else {
-                    HashMap::new() // Return empty if reference not found
-                }
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "refimage".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(&format!(r"^!\[({})]\s*\[([^\]]*)]", *LINK_INSIDE)).unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string(), caps[2].to_string()])
-            }),
-            parse: Some(|capture, state| {
-                if let Some(def) = state._defs.get(&capture[1].to_lowercase()) {
-                    HashMap::from([
-                        ("type".to_string(), "image".to_string()),
-                        ("alt".to_string(), capture[0].clone()),
-                        ("target".to_string(), def["target"].clone()),
-                        (
-                            "title".to_string(),
-                            def.get("title").cloned().unwrap_or_default(),
-                        ),
-                    ])
-                } else {
-                    HashMap::new() // Return empty if reference not found
-                }
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "em".to_string(),
-        Rule {
-            order: Some({curr_order += 1.0; curr_order}),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^\b_((?:__|\\[\s\S]|[^\\_])+?)_\b|^\*(?=\S)((?:\*\*|\\[\s\S]|\s+(?:\\[\s\S]|[^\s\*\\]|\*\*)|[^\s\*\\])+?)\*(?!\*)").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps.get(2).or_else(|| caps.get(1)).unwrap().as_str().to_string(), caps.get(0).unwrap().as_str().to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "em".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                    ("inline".to_string(), "true".to_string()),
-                ])
-            }),
-            capture_len: Some(|capture| capture[1].len()),
-        },
-    );
-
-    rules.insert(
-        "strong".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^(\*\*|__)((?:\\[\s\S]|[^\\]|(?!\1)[*_])+?)\1").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string(), caps[2].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "strong".to_string()),
-                    ("content".to_string(), capture[1].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    // Replace the u rule implementation
-    rules.insert(
-        "u".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^__((?:\\[\S\s]|[^\\])+?)__(?!_)").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string(), caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "u".to_string()),
-                    ("content".to_string(), capture[1].clone()),
-                    ("inline".to_string(), "true".to_string()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "del".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex =
-                    Regex::new(r"^~~(?=\S)((?:\\[\S\s]|~(?!~)|[^\s\\~]|\s(?!~~))+?)~~").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "del".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                    ("inline".to_string(), "true".to_string()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "inlineCode".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^(`+)([\S\s]*?[^`])\1(?!`)").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string(), caps[2].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "inlineCode".to_string()),
-                    ("content".to_string(), capture[1].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "br".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex = Regex::new(r"^ {2,}\n").unwrap();
-                regex.captures(source).ok().flatten().map(|_| vec![])
-            }),
-            parse: Some(|_capture, _state| HashMap::from([("type".to_string(), "br".to_string())])),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "paragraph".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, state| {
-                if state.inline {
-                    return None;
-                }
-
-                let regex = Regex::new(r"^((?:[^\n]|\n(?! *\n))+)(?:\n *)+\n").unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[1].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "paragraph".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules.insert(
-        "text".to_string(),
-        Rule {
-            order: Some({
-                curr_order += 1.0;
-                curr_order
-            }),
-            match_fn: Some(|source, _state| {
-                let regex =
-                    Regex::new(r"^[\S\s]+?(?=[^\d\sA-Za-z\u00C0-\uFFFF]|\n\n| {2,}\n|\w+:\S|$)")
-                        .unwrap();
-                regex
-                    .captures(source)
-                    .ok()
-                    .flatten()
-                    .map(|caps| vec![caps[0].to_string()])
-            }),
-            parse: Some(|capture, _state| {
-                HashMap::from([
-                    ("type".to_string(), "text".to_string()),
-                    ("content".to_string(), capture[0].clone()),
-                    ("inline".to_string(), "true".to_string()),
-                ])
-            }),
-            capture_len: None,
-        },
-    );
-
-    rules
-}
+use fancy_regex::Regex;
+use std::collections::HashMap;
+
+use crate::output::{register_outputs, OutputFunction};
+use crate::{
+    email, html, jira, latex, markdown, matrix, plain_text, roff, slack, telegram, terminal,
+};
+
+fn unescape_url(raw_url: &str) -> String {
+    ESCAPED_CHAR_R.replace_all(raw_url, "$1").to_string()
+}
+
+lazy_static::lazy_static! {
+    static ref LIST_BULLET: &'static str = r"(?:[*+-]|\d+\.)";
+    static ref LIST_ITEM_PREFIX: &'static str = r"( *)(?:[*+-]|\d+\.) +";
+    static ref LIST_ITEM_PREFIX_R: Regex = Regex::new(&format!(r"^{}", *LIST_ITEM_PREFIX)).unwrap();
+    static ref LIST_ITEM_R: Regex = Regex::new(&format!(
+        r"(?m)^{}[^\n]*(?:\n(?!{} )[^\n]*)*(\n|$)",
+        *LIST_ITEM_PREFIX,
+        *LIST_BULLET
+    )).unwrap();
+    static ref BLOCK_END_R: Regex = Regex::new(r"\n{2,}$").unwrap();
+    static ref LIST_BLOCK_END_R: Regex = Regex::new(r"\n{2,}$").unwrap();
+    static ref LIST_ITEM_END_R: Regex = Regex::new(r" *\n+$").unwrap();
+    static ref LIST_R: Regex = Regex::new(&format!(
+        r"^( *)({})[^\S\n][\s\S]+?(?:\n{{2,}}(?! )(?!\1{} )\n*|\s*\n*$)",
+        *LIST_BULLET,
+        *LIST_BULLET
+    )).unwrap();
+    static ref NESTED_LIST_INDENT_R: Regex = Regex::new(&format!(
+        r"\n( +){} ",
+        *LIST_BULLET
+    )).unwrap();
+    static ref LIST_LOOKBEHIND_R: Regex = Regex::new(r"(?:^|\n)( *)$").unwrap();
+    static ref INLINE_CODE_ESCAPE_BACKTICKS_R: Regex = Regex::new(r"^ (?= *`)|(` *) $").unwrap();
+    static ref LINK_INSIDE: &'static str = r#"(?:\[[^\]]*\]|[^\[\]]|\](?=[^\[]*\]))*"#;
+    static ref LINK_HREF_AND_TITLE: &'static str = r#"\s*<?((?:\([^)]*\)|[^\s\\]|\\.)*?)>?(?:\s+['"]([\s\S]*?)['"])?\s*"#;
+    static ref AUTOLINK_MAILTO_CHECK_R: Regex = Regex::new("(?i)mailto:").unwrap();
+    static ref CODE_BLOCK_INDENT_R: Regex = Regex::new(r"(?m)^ {4}").unwrap();
+    static ref PARAGRAPH_START_R: Regex = Regex::new(r"^(?:[^\n]|\n(?! *\n))+(?:\n *)+\n").unwrap();
+    static ref BLOCK_QUOTE_PREFIX_R: Regex = Regex::new(r"(?m)^ *> ?").unwrap();
+    // Code spans inside a spoiler are skipped over, so pipes in them don't end it
+    static ref SPOILER_R: Regex =
+        Regex::new(r"^\|\|((?:\\[\s\S]|(`+)[\s\S]*?[^`]\2(?!`)|[\s\S])+?)\|\|").unwrap();
+    static ref EMOJI_R: Regex = Regex::new(r"^<(a?):(\w+):(\d+)>").unwrap();
+    static ref MENTION_R: Regex =
+        Regex::new(r"^<(@!?|@&|#)(\d+)>|^@(everyone|here)\b").unwrap();
+    static ref TASK_ITEM_R: Regex = Regex::new(r"^\[([ xX])\](?: +|$)").unwrap();
+    static ref FOOTNOTE_DEF_R: Regex = Regex::new(
+        r"^ *\[\^([^\]\s]+)\]: *([^\n]*(?:\n(?: *\n)* {4}[^\n]*|\n(?! *\n)(?! *\[\^[^\]\s]+\]:)(?! {0,3}(?:[*+-]|\d+\.|#+) )[^\n]*)*)\n*"
+    ).unwrap();
+    static ref FOOTNOTE_INDENT_R: Regex = Regex::new(r"(?m)^ {1,4}").unwrap();
+    static ref FOOTNOTE_REF_R: Regex = Regex::new(r"^\[\^([^\]\s]+)\]").unwrap();
+    static ref TABLE_R: Regex = Regex::new(
+        r"^ *(\|[^\n]+)\n *\|( *[-:]+[-| :]*)\n((?: *\|[^\n]*(?:\n|$))*)\n*"
+    ).unwrap();
+    static ref NPTABLE_R: Regex = Regex::new(
+        r"^ *(\S[^\n]*\|[^\n]*)\n *([-:]+ *\|[-| :]*)\n((?:[^\n]*\|[^\n]*(?:\n|$))*)\n*"
+    ).unwrap();
+    static ref ESCAPED_CHAR_R: Regex = Regex::new(r"\\([^\d\sA-Za-z])").unwrap();
+    static ref HEADING_R: Regex = Regex::new(r"^( *(#{1,6})([^\n]+?)#* *)(?:\n *)+\n").unwrap();
+    static ref LHEADING_R: Regex = Regex::new(r"^(([^\n]+)\n *(=|-){3,} *)(?:\n *)+\n").unwrap();
+    static ref HR_R: Regex = Regex::new(r"^( *[*_-]){3,} *(?:\n *)+\n").unwrap();
+    static ref CODE_BLOCK_R: Regex = Regex::new(r"^(?: {4}[^\n]+\n*)+(?:\n *)+\n").unwrap();
+    static ref FENCE_R: Regex = Regex::new(
+        r"^( *(`{3,}|~{3,}) *(?:(\S+) *)?\n([\S\s]+?)\n?\2 *)(?:\n *)+\n"
+    ).unwrap();
+    static ref BLOCK_QUOTE_R: Regex = Regex::new(r"^( *>[^\n]+(\n[^\n]+)*\n*)+\n{2,}").unwrap();
+    static ref DEF_R: Regex = Regex::new(
+        r#"^ *\[([^\]]+)]: *<?([^\s>]*)>?(?: +["(]([^\n]+)[")])?(?:\n *)+$"#
+    ).unwrap();
+    static ref NEWLINE_R: Regex = Regex::new(r"^(?:\n *)*\n").unwrap();
+    static ref ESCAPE_R: Regex = Regex::new(r"^\\([^\d\sA-Za-z])").unwrap();
+    static ref AUTOLINK_R: Regex = Regex::new(r"^<([^ :>]+:\/[^ >]+)>").unwrap();
+    static ref MAILTO_R: Regex = Regex::new(r"^<([^ >]+@[^ >]+)>").unwrap();
+    static ref URL_R: Regex = Regex::new(r#"^(https?:\/\/[^\s<]+[^:\s<"'\)\]}])"#).unwrap();
+    static ref LINK_R: Regex = Regex::new(&format!(
+        r#"^\[({})]\({}\)"#,
+        *LINK_INSIDE, *LINK_HREF_AND_TITLE
+    )).unwrap();
+    static ref IMAGE_R: Regex = Regex::new(&format!(
+        r#"^!\[({})]\({}\)"#,
+        *LINK_INSIDE, *LINK_HREF_AND_TITLE
+    )).unwrap();
+    static ref REFLINK_R: Regex = Regex::new(&format!(
+        r"^\[({})]\s*\[([^\]]*)]",
+        *LINK_INSIDE
+    )).unwrap();
+    static ref REFIMAGE_R: Regex = Regex::new(&format!(
+        r"^!\[({})]\s*\[([^\]]*)]",
+        *LINK_INSIDE
+    )).unwrap();
+    static ref EM_R: Regex = Regex::new(
+        r"^\b_((?:__|\\[\s\S]|[^\\_])+?)_\b|^\*(?=\S)((?:\*\*|\\[\s\S]|\s+(?:\\[\s\S]|[^\s\*\\]|\*\*)|[^\s\*\\])+?)\*(?!\*)"
+    ).unwrap();
+    static ref STRONG_R: Regex = Regex::new(
+        r"^(\*\*|__)((?:\\[\s\S]|[^\\]|(?!\1)[*_])+?)\1"
+    ).unwrap();
+    static ref U_R: Regex = Regex::new(r"^__((?:\\[\S\s]|[^\\])+?)__(?!_)").unwrap();
+    static ref DEL_R: Regex = Regex::new(
+        r"^~~(?=\S)((?:\\[\S\s]|~(?!~)|[^\s\\~]|\s(?!~~))+?)~~"
+    ).unwrap();
+    static ref INLINE_CODE_R: Regex = Regex::new(r"^(`+)([\S\s]*?[^`])\1(?!`)").unwrap();
+    static ref BR_R: Regex = Regex::new(r"^ {2,}\n").unwrap();
+    static ref PARAGRAPH_R: Regex = Regex::new(r"^((?:[^\n]|\n(?! *\n))+)(?:\n *)+\n").unwrap();
+    static ref TEXT_R: Regex =
+        Regex::new(r"^[\S\s]+?(?=[^\d\sA-Za-z\u00C0-\uFFFF]|\n\n| {2,}\n|\w+:\S|$)").unwrap();
+    // Inside list items, text also stops before ordered bullets, so nested ordered
+    // lists in tight items start a list like nested bullet lists do
+    static ref LIST_ITEM_TEXT_R: Regex = Regex::new(
+        r"^[\S\s]+?(?=[^\d\sA-Za-z\u00C0-\uFFFF]|\n\n| {2,}\n|\n *\d+\. |\w+:\S|$)"
+    ).unwrap();
+}
+
+pub type RuleMap = HashMap<String, Rule>;
+pub type ASTNode = HashMap<String, String>;
+pub type ParserClosure = Box<dyn Fn(&str, Option<State>) -> Vec<ASTNode>>;
+pub type MatchFunction = fn(&str, &mut State) -> Option<Vec<String>>;
+pub type ParseFunction = fn(Vec<String>, &mut State) -> ASTNode;
+pub type CaptureLengthFunction = fn(&Vec<String>) -> usize;
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub order: Option<f64>,
+    pub match_fn: Option<MatchFunction>,
+    pub parse: Option<ParseFunction>,
+    pub capture_len: Option<CaptureLengthFunction>,
+    /// Output functions keyed by target format (e.g. `"html"`), used by `output_for`.
+    pub output: HashMap<String, OutputFunction>,
+}
+
+// Update State type to include boolean flags and refs
+#[derive(Default, Clone)]
+pub struct State {
+    pub inline: bool,
+    pub _list: bool,
+    pub in_link: bool,
+    /// Give each parsed node its span in the source as a JSON-encoded unist
+    /// `position`. Only set it for the document itself: nested content is
+    /// parsed on its own, so its spans wouldn't be document positions.
+    pub positions: bool,
+    pub prev_capture: Option<String>,
+    pub _refs: HashMap<String, Vec<ASTNode>>,
+    pub _defs: HashMap<String, ASTNode>,
+    pub data: HashMap<String, String>,
+}
+
+/// Returns the content of a list item as the `list` rule stores it, without
+/// its bullet, its indentation and the blank lines after it.
+pub(crate) fn list_item_content(item: &str) -> String {
+    let space = LIST_ITEM_PREFIX_R
+        .find(item)
+        .ok()
+        .flatten()
+        .map_or(0, |prefix| prefix.as_str().len());
+    let content = item
+        .split('\n')
+        .map(|line| {
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            &line[indent.min(space)..]
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let content = LIST_ITEM_PREFIX_R.replace(&content, "");
+    // Nested lists may be indented further than the item content, so
+    // dedent them to keep the extra indentation out of the text before them
+    let content = match NESTED_LIST_INDENT_R.captures(&content).ok().flatten() {
+        Some(caps) => {
+            let start = caps.get(0).map_or(0, |m| m.start() + 1);
+            let indent_r = Regex::new(&format!(r"(?m)^ {{1,{}}}", caps[1].len())).unwrap();
+            format!(
+                "{}{}",
+                &content[..start],
+                indent_r.replace_all(&content[start..], "")
+            )
+        }
+        None => content.to_string(),
+    };
+    LIST_ITEM_END_R.replace(&content, "").to_string()
+}
+
+/// Removes the `[ ]` or `[x]` marker from the content of a task list item.
+pub(crate) fn strip_task_marker(content: &str) -> &str {
+    match TASK_ITEM_R.find(content).ok().flatten() {
+        Some(marker) => &content[marker.end()..],
+        None => content,
+    }
+}
+
+/// Returns the source of a list item with the given content, the way the
+/// `list` rule stores it: `bullet` and `marker` go before the first line and
+/// the other lines are indented to line up with it.
+pub(crate) fn list_item_source(bullet: &str, marker: &str, content: &str) -> String {
+    let indent = " ".repeat(bullet.len());
+    let mut source = format!("{}{}", bullet, marker);
+    for (i, line) in content.split('\n').enumerate() {
+        if i > 0 {
+            source.push('\n');
+            if !line.is_empty() {
+                source.push_str(&indent);
+            }
+        }
+        source.push_str(line);
+    }
+    source.push('\n');
+    source
+}
+
+/// Splits a table row into the source of its cells, on pipes that aren't
+/// escaped. Pipes at the start and end of the row are optional.
+fn split_table_row(row: &str) -> Vec<String> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut escaped = false;
+    for c in row.chars() {
+        if c == '|' && !escaped {
+            cells.push(cell.trim().to_string());
+            cell.clear();
+        } else {
+            cell.push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    if !cell.trim().is_empty() {
+        cells.push(cell.trim().to_string());
+    }
+    cells
+}
+
+/// Builds a `table` node from the header, alignment and body rows captured
+/// by the `table` and `nptable` rules. Body rows are cut or padded with empty
+/// cells to the number of header cells, as GitHub does.
+fn parse_table(capture: &[String]) -> ASTNode {
+    let header = split_table_row(&capture[0]);
+    let align: Vec<&str> = split_table_row(&capture[1])
+        .iter()
+        .map(|cell| match (cell.starts_with(':'), cell.ends_with(':')) {
+            (true, true) => "center",
+            (false, true) => "right",
+            (true, false) => "left",
+            (false, false) => "",
+        })
+        .collect();
+    let cells: Vec<Vec<String>> = capture[2]
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut row = split_table_row(line);
+            row.resize(header.len(), String::new());
+            row
+        })
+        .collect();
+    HashMap::from([
+        ("type".to_string(), "table".to_string()),
+        (
+            "header".to_string(),
+            serde_json::to_string(&header).unwrap(),
+        ),
+        ("align".to_string(), serde_json::to_string(&align).unwrap()),
+        ("cells".to_string(), serde_json::to_string(&cells).unwrap()),
+    ])
+}
+
+pub fn create_default_rules() -> HashMap<String, Rule> {
+    let mut rules = HashMap::new();
+    let mut curr_order = 0.0;
+
+    rules.insert(
+        "heading".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                HEADING_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps[3].trim().to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "heading".to_string()),
+                    ("level".to_string(), capture[1].len().to_string()),
+                    ("content".to_string(), capture[2].trim().to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "nptable".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.inline {
+                    return None;
+                }
+
+                NPTABLE_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps[3].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| parse_table(&capture[1..])),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "lheading".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                LHEADING_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps[3].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "heading".to_string()),
+                    (
+                        "level".to_string(),
+                        if capture[2] == "=" { "1" } else { "2" }.to_string(),
+                    ),
+                    ("content".to_string(), capture[1].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "hr".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                HR_R.captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|_capture, _state| HashMap::from([("type".to_string(), "hr".to_string())])),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "codeBlock".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                CODE_BLOCK_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                let content = CODE_BLOCK_INDENT_R
+                    .replace_all(&capture[0], "")
+                    .trim_end_matches('\n')
+                    .to_string();
+                HashMap::from([
+                    ("type".to_string(), "codeBlock".to_string()),
+                    ("content".to_string(), content),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "fence".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                FENCE_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[1].to_string(),
+                        caps.get(3)
+                            .map_or("".to_string(), |m| m.as_str().to_string()),
+                        caps[4].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "codeBlock".to_string()),
+                    ("lang".to_string(), capture[1].clone()),
+                    ("content".to_string(), capture[2].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "blockQuote".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                BLOCK_QUOTE_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                let content = BLOCK_QUOTE_PREFIX_R
+                    .replace_all(&capture[0], "")
+                    .to_string();
+                HashMap::from([
+                    ("type".to_string(), "blockQuote".to_string()),
+                    ("content".to_string(), content),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "list".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                let prev_capture = state.prev_capture.as_deref().unwrap_or("").to_string();
+
+                if let Ok(Some(lookbehind)) = LIST_LOOKBEHIND_R.captures(&prev_capture) {
+                    if state._list || !state.inline {
+                        let indent = lookbehind.get(1).map_or("", |m| m.as_str());
+                        let updated_source = format!("{}{}", indent, source);
+                        return LIST_R.captures(&updated_source).ok().flatten().map(|caps| {
+                            vec![
+                                caps[0].to_string(),
+                                caps[1].to_string(),
+                                caps[2].to_string(),
+                                indent.to_string(),
+                            ]
+                        });
+                    }
+                }
+                None
+            }),
+            parse: Some(|capture, _state| {
+                let bullet = &capture[2];
+                let ordered = bullet.len() > 1;
+                let start: Option<String> = if ordered {
+                    bullet.trim_end_matches('.').parse().ok()
+                } else {
+                    None
+                };
+
+                let items_str = LIST_BLOCK_END_R.replace(&capture[0], "\n");
+                let mut items = Vec::new();
+                let mut checked: Vec<Option<bool>> = Vec::new();
+
+                let regex_matches = LIST_ITEM_R.find_iter(&items_str);
+                for m in regex_matches.flatten() {
+                    let item = m.as_str();
+                    // GFM task list items start with `[ ]` or `[x]`
+                    checked.push(
+                        TASK_ITEM_R
+                            .captures(&list_item_content(item))
+                            .ok()
+                            .flatten()
+                            .map(|caps| &caps[1] != " "),
+                    );
+                    items.push(item.to_string());
+                }
+
+                let mut node = HashMap::from([
+                    ("type".to_string(), "list".to_string()),
+                    ("ordered".to_string(), ordered.to_string()),
+                    (
+                        "start".to_string(),
+                        start.map_or("".to_string(), |n| n.to_string()),
+                    ),
+                    ("items".to_string(), serde_json::to_string(&items).unwrap()),
+                ]);
+                if checked.iter().any(Option::is_some) {
+                    node.insert(
+                        "checked".to_string(),
+                        serde_json::to_string(&checked).unwrap(),
+                    );
+                }
+                node
+            }),
+            // The indentation borrowed from the previous capture was already consumed
+            capture_len: Some(|capture| capture[0].len() - capture[3].len()),
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "footnoteDef".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.inline {
+                    return None;
+                }
+                FOOTNOTE_DEF_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                // Following paragraphs are indented by four spaces, like list items
+                let content = FOOTNOTE_INDENT_R.replace_all(&capture[2], "");
+                HashMap::from([
+                    ("type".to_string(), "footnoteDef".to_string()),
+                    ("label".to_string(), capture[1].to_lowercase()),
+                    ("content".to_string(), content.trim_end().to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "def".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                DEF_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps.get(3)
+                            .map_or("".to_string(), |m| m.as_str().to_string()),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                let def = capture[0].replace(r"\s+", " ").to_lowercase();
+                let target = capture[1].clone();
+                let title = capture[2].clone();
+
+                HashMap::from([
+                    ("type".to_string(), "def".to_string()),
+                    ("def".to_string(), def),
+                    ("target".to_string(), target),
+                    ("title".to_string(), title),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "table".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.inline {
+                    return None;
+                }
+
+                TABLE_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps[3].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| parse_table(&capture[1..])),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "newline".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                NEWLINE_R.captures(source).ok().flatten().map(|_| vec![])
+            }),
+            parse: Some(|_capture, _state| {
+                HashMap::from([("type".to_string(), "newline".to_string())])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "escape".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                // An escape starting a paragraph belongs to it, e.g. `\# not a heading`
+                if !state.inline && PARAGRAPH_START_R.is_match(source).unwrap_or(false) {
+                    return None;
+                }
+
+                ESCAPE_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "text".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                ])
+            }),
+            capture_len: Some(|capture| 1 + capture[0].len()),
+            output: HashMap::new(),
+        },
+    );
+
+    // Ahead of `autolink` and `mailto`, which would otherwise see the angle
+    // brackets of mentions and custom emoji first
+    rules.insert(
+        "mention".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                let caps = MENTION_R.captures(source).ok().flatten()?;
+                let Some(id) = caps.get(2) else {
+                    // `@everyone` in the middle of a word, e.g. an address, is text
+                    let prev_capture = state.prev_capture.as_deref().unwrap_or("");
+                    if prev_capture.ends_with(|c: char| c.is_alphanumeric()) {
+                        return None;
+                    }
+                    return Some(vec![
+                        caps[0].to_string(),
+                        caps[3].to_string(),
+                        String::new(),
+                    ]);
+                };
+                let kind = match &caps[1] {
+                    "#" => "channel",
+                    "@&" => "role",
+                    _ => "user",
+                };
+                Some(vec![
+                    caps[0].to_string(),
+                    kind.to_string(),
+                    id.as_str().to_string(),
+                ])
+            }),
+            parse: Some(|capture, _state| {
+                let mut node = HashMap::from([
+                    ("type".to_string(), "mention".to_string()),
+                    ("kind".to_string(), capture[1].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ]);
+                if !capture[2].is_empty() {
+                    node.insert("id".to_string(), capture[2].clone());
+                }
+                node
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "emoji".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                EMOJI_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps[3].to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "emoji".to_string()),
+                    ("name".to_string(), capture[2].clone()),
+                    ("id".to_string(), capture[3].clone()),
+                    ("animated".to_string(), (capture[1] == "a").to_string()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "autolink".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.in_link {
+                    return None;
+                }
+
+                AUTOLINK_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "link".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                    ("target".to_string(), capture[0].clone()),
+                ])
+            }),
+            capture_len: Some(|capture| capture[0].len() + 2),
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "mailto".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.in_link {
+                    return None;
+                }
+
+                MAILTO_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                let address = capture[0].clone();
+                let target = if !AUTOLINK_MAILTO_CHECK_R.is_match(&address).unwrap_or(false) {
+                    format!("mailto:{}", address)
+                } else {
+                    address.clone()
+                };
+                HashMap::from([
+                    ("type".to_string(), "link".to_string()),
+                    ("content".to_string(), address),
+                    ("target".to_string(), target),
+                ])
+            }),
+            capture_len: Some(|capture| capture[0].len() + 2),
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "url".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.in_link {
+                    return None;
+                }
+
+                URL_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "link".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                    ("target".to_string(), capture[0].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "footnoteRef".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                FOOTNOTE_REF_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string(), caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "footnoteRef".to_string()),
+                    ("label".to_string(), capture[1].to_lowercase()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    // Added by `collect_footnotes` rather than parsed, so it only has outputs
+    rules.insert(
+        "footnotes".to_string(),
+        Rule {
+            order: None,
+            match_fn: None,
+            parse: None,
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    // Replace the link rule implementation
+    rules.insert(
+        "link".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                LINK_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps.get(3)
+                            .map_or("".to_string(), |m| m.as_str().to_string()),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "link".to_string()),
+                    ("content".to_string(), capture[1].clone()),
+                    ("target".to_string(), unescape_url(&capture[2])),
+                    ("title".to_string(), capture[3].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "image".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                IMAGE_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps[0].to_string(),
+                        caps[1].to_string(),
+                        caps[2].to_string(),
+                        caps.get(3)
+                            .map_or("".to_string(), |m| m.as_str().to_string()),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "image".to_string()),
+                    ("alt".to_string(), capture[1].clone()),
+                    ("target".to_string(), unescape_url(&capture[2])),
+                    ("title".to_string(), capture[3].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "reflink".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                REFLINK_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string(), caps[2].to_string()])
+            }),
+            parse: Some(|capture, state| {
+                if let Some(def) = state._defs.get(&capture[1].to_lowercase()) {
+                    HashMap::from([
+                        ("type".to_string(), "link".to_string()),
+                        ("content".to_string(), capture[0].clone()),
+                        ("target".to_string(), def["target"].clone()),
+                        (
+                            "title".to_string(),
+                            def.get("title").cloned().unwrap_or_default(),
+                        ),
+                    ])
+                } else {
+                    HashMap::new() // Return empty if reference not found
+                }
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "refimage".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                REFIMAGE_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string(), caps[2].to_string()])
+            }),
+            parse: Some(|capture, state| {
+                if let Some(def) = state._defs.get(&capture[1].to_lowercase()) {
+                    HashMap::from([
+                        ("type".to_string(), "image".to_string()),
+                        ("alt".to_string(), capture[0].clone()),
+                        ("target".to_string(), def["target"].clone()),
+                        (
+                            "title".to_string(),
+                            def.get("title").cloned().unwrap_or_default(),
+                        ),
+                    ])
+                } else {
+                    HashMap::new() // Return empty if reference not found
+                }
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "em".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                EM_R.captures(source).ok().flatten().map(|caps| {
+                    vec![
+                        caps.get(2)
+                            .or_else(|| caps.get(1))
+                            .unwrap()
+                            .as_str()
+                            .to_string(),
+                        caps.get(0).unwrap().as_str().to_string(),
+                    ]
+                })
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "em".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: Some(|capture| capture[1].len()),
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "strong".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                STRONG_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string(), caps[2].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "strong".to_string()),
+                    ("content".to_string(), capture[1].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    // Replace the u rule implementation
+    rules.insert(
+        "u".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                U_R.captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string(), caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "u".to_string()),
+                    ("content".to_string(), capture[1].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "spoiler".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                SPOILER_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string(), caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "spoiler".to_string()),
+                    ("content".to_string(), capture[1].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "del".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                DEL_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "del".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: Some(|capture| 4 + capture[0].len()),
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "inlineCode".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                INLINE_CODE_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string(), caps[2].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "inlineCode".to_string()),
+                    ("content".to_string(), capture[1].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "br".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, _state| {
+                BR_R.captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|_capture, _state| HashMap::from([("type".to_string(), "br".to_string())])),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "paragraph".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                if state.inline {
+                    return None;
+                }
+
+                PARAGRAPH_R
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[1].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "paragraph".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    rules.insert(
+        "text".to_string(),
+        Rule {
+            order: Some({
+                curr_order += 1.0;
+                curr_order
+            }),
+            match_fn: Some(|source, state| {
+                let regex = if state._list {
+                    &*LIST_ITEM_TEXT_R
+                } else {
+                    &*TEXT_R
+                };
+                regex
+                    .captures(source)
+                    .ok()
+                    .flatten()
+                    .map(|caps| vec![caps[0].to_string()])
+            }),
+            parse: Some(|capture, _state| {
+                HashMap::from([
+                    ("type".to_string(), "text".to_string()),
+                    ("content".to_string(), capture[0].clone()),
+                    ("inline".to_string(), "true".to_string()),
+                ])
+            }),
+            capture_len: None,
+            output: HashMap::new(),
+        },
+    );
+
+    register_outputs(&mut rules, "html", html::outputs());
+    register_outputs(&mut rules, "text", plain_text::outputs());
+    register_outputs(&mut rules, "terminal", terminal::outputs());
+    register_outputs(&mut rules, "latex", latex::outputs());
+    register_outputs(&mut rules, "roff", roff::outputs());
+    register_outputs(&mut rules, "slack", slack::outputs());
+    register_outputs(&mut rules, "telegram", telegram::outputs());
+    register_outputs(&mut rules, "telegramHtml", telegram::html_outputs());
+    register_outputs(&mut rules, "matrix", matrix::outputs());
+    register_outputs(&mut rules, "jira", jira::outputs());
+    register_outputs(&mut rules, "markdown", markdown::outputs());
+    register_outputs(&mut rules, "email", email::outputs());
+
+    rules
+}
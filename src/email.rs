@@ -0,0 +1,482 @@
+use fancy_regex::Regex;
+use std::rc::Rc;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::{emoji_text, emoji_url, EmojiOptions};
+use crate::footnotes::{footnote_items, next_reference, output_footnote, reference_source};
+use crate::html::{html_tag, sanitize_text, sanitize_url};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, table_align, task_marker,
+    OutputFunction, OutputState,
+};
+
+lazy_static::lazy_static! {
+    static ref SCHEME_R: Regex = Regex::new(r"^[A-Za-z][A-Za-z0-9+.\-]*:").unwrap();
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Colours and fonts of `to_email_html`, as CSS values.
+#[derive(Debug, Clone)]
+pub struct EmailTheme {
+    pub text_color: String,
+    pub link_color: String,
+    /// Colour of block quote text.
+    pub muted_color: String,
+    /// Colour of block quote bars and horizontal rules.
+    pub border_color: String,
+    pub code_color: String,
+    pub code_background: String,
+    pub font_family: String,
+    pub code_font_family: String,
+    /// Size of body text in pixels. Headings and code are sized relative to it.
+    pub font_size: u32,
+}
+
+impl Default for EmailTheme {
+    fn default() -> Self {
+        EmailTheme {
+            text_color: "#24292f".to_string(),
+            link_color: "#0969da".to_string(),
+            muted_color: "#57606a".to_string(),
+            border_color: "#d0d7de".to_string(),
+            code_color: "#24292f".to_string(),
+            code_background: "#f6f8fa".to_string(),
+            font_family: "-apple-system, 'Segoe UI', Helvetica, Arial, sans-serif".to_string(),
+            code_font_family: "Menlo, Consolas, 'Courier New', monospace".to_string(),
+            font_size: 16,
+        }
+    }
+}
+
+/// Options for `to_email_html`.
+#[derive(Debug, Clone, Default)]
+pub struct EmailOptions {
+    pub theme: EmailTheme,
+    /// Url relative link and image targets are resolved against, e.g.
+    /// `https://example.com/docs/`. Mail clients have no page to resolve them
+    /// against, so relative targets are left out when this is empty.
+    pub base_url: String,
+}
+
+/// Builds a `style` attribute value from CSS declarations.
+fn style(declarations: &[(&str, &str)]) -> String {
+    declarations
+        .iter()
+        .map(|(property, value)| format!("{}:{};", property, value))
+        .collect()
+}
+
+fn px(size: f64) -> String {
+    format!("{}px", size.round())
+}
+
+/// Resolves `url` against `base`, like a browser resolves a relative link on
+/// the page at `base`.
+fn absolute_url(url: &str, base: &str) -> Option<String> {
+    let url = url.trim();
+    if SCHEME_R.is_match(url).unwrap_or(false) {
+        return Some(url.to_string());
+    }
+    let scheme_end = base.find("://")?;
+    if let Some(rest) = url.strip_prefix("//") {
+        return Some(format!("{}//{}", &base[..scheme_end + 1], rest));
+    }
+    let path_start = base[scheme_end + 3..]
+        .find('/')
+        .map_or(base.len(), |i| scheme_end + 3 + i);
+    let origin = &base[..path_start];
+    let base_path = base[path_start..].split(['?', '#']).next().unwrap_or("");
+    if url.starts_with('/') {
+        Some(format!("{}{}", origin, url))
+    } else if url.starts_with(['?', '#']) {
+        let base = base.split(url.chars().next().unwrap_or('#')).next()?;
+        Some(format!("{}{}", base, url))
+    } else {
+        let directory = base_path.rfind('/').map_or("/", |i| &base_path[..=i]);
+        Some(format!("{}{}{}", origin, directory, url))
+    }
+}
+
+fn options(state: &OutputState) -> Rc<EmailOptions> {
+    state.options.get()
+}
+
+/// Returns the absolute, safe url of a link or image target, or an empty
+/// string if there is none.
+fn email_url(target: &str, state: &OutputState) -> String {
+    absolute_url(target, &options(state).base_url)
+        .and_then(|url| sanitize_url(&url))
+        .unwrap_or_default()
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let options = options(state);
+            let theme = &options.theme;
+            let scale = match node_value(node, "level") {
+                "1" => 2.0,
+                "2" => 1.5,
+                "3" => 1.25,
+                "4" => 1.0,
+                "5" => 0.875,
+                _ => 0.85,
+            };
+            let heading_style = style(&[
+                ("margin", "24px 0 16px"),
+                ("font-family", &theme.font_family),
+                ("font-size", &px(theme.font_size as f64 * scale)),
+                ("font-weight", "600"),
+                ("line-height", "1.25"),
+                ("color", &theme.text_color),
+            ]);
+            html_tag(
+                &format!("h{}", node_value(node, "level")),
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("style", &heading_style)],
+                true,
+            )
+        }),
+        ("hr", |_node, _nested, state| {
+            let border = format!("1px solid {}", options(state).theme.border_color);
+            let hr_style = style(&[
+                ("border", "0"),
+                ("border-top", &border),
+                ("margin", "24px 0"),
+            ]);
+            html_tag("hr", "", &[("style", &hr_style)], false)
+        }),
+        ("codeBlock", |node, _nested, state| {
+            let options = options(state);
+            let theme = &options.theme;
+            // Mail clients ignore most CSS on `pre`, so the background is
+            // drawn by a table cell around it
+            let pre_style = style(&[
+                ("margin", "0"),
+                ("font-family", &theme.code_font_family),
+                ("font-size", &px(theme.font_size as f64 * 0.85)),
+                ("line-height", "1.45"),
+                ("color", &theme.code_color),
+                ("white-space", "pre-wrap"),
+                ("word-wrap", "break-word"),
+            ]);
+            let pre = html_tag(
+                "pre",
+                &sanitize_text(node_value(node, "content")),
+                &[("style", &pre_style)],
+                true,
+            );
+            let cell_style = style(&[
+                ("padding", "12px 16px"),
+                ("background-color", &theme.code_background),
+                ("border-radius", "6px"),
+            ]);
+            let cell = html_tag(
+                "td",
+                &pre,
+                &[("bgcolor", &theme.code_background), ("style", &cell_style)],
+                true,
+            );
+            html_tag(
+                "table",
+                &html_tag("tr", &cell, &[], true),
+                &[
+                    ("role", "presentation"),
+                    ("width", "100%"),
+                    ("cellpadding", "0"),
+                    ("cellspacing", "0"),
+                    ("border", "0"),
+                    ("style", "border-collapse:collapse;margin:0 0 16px;"),
+                ],
+                true,
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let border = format!("4px solid {}", options(state).theme.border_color);
+            let quote_style = style(&[
+                ("margin", "0 0 16px"),
+                ("padding", "0 16px"),
+                ("border-left", &border),
+                ("color", &options(state).theme.muted_color),
+            ]);
+            html_tag(
+                "blockquote",
+                &output_block(nested, node_value(node, "content"), state),
+                &[("style", &quote_style)],
+                true,
+            )
+        }),
+        ("list", |node, nested, state| {
+            // Mail clients drop form controls, so tasks get a ballot box character
+            let items: String = list_items(node)
+                .iter()
+                .zip(list_checked(node))
+                .map(|(item, checked)| {
+                    let content = output_list_item(node, nested, item, state);
+                    html_tag(
+                        "li",
+                        &format!("{}{}", task_marker(checked, "☐ ", "☑ "), content),
+                        &[("style", "margin:0 0 4px;")],
+                        true,
+                    )
+                })
+                .collect();
+            let list_style = style(&[("margin", "0 0 16px"), ("padding-left", "32px")]);
+            if node_value(node, "ordered") == "true" {
+                html_tag(
+                    "ol",
+                    &items,
+                    &[("start", node_value(node, "start")), ("style", &list_style)],
+                    true,
+                )
+            } else {
+                html_tag("ul", &items, &[("style", &list_style)], true)
+            }
+        }),
+        ("table", |node, nested, state| {
+            let border = format!("1px solid {}", options(state).theme.border_color);
+            let align = table_align(node);
+            let rows = output_table_rows(node, nested, state);
+            let rows: String = rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let tag = if i == 0 { "th" } else { "td" };
+                    let cells: String = row
+                        .iter()
+                        .enumerate()
+                        .map(|(column, cell)| {
+                            let text_align = match align.get(column).map(String::as_str) {
+                                Some("") | None => "left",
+                                Some(align) => align,
+                            };
+                            let cell_style = style(&[
+                                ("padding", "6px 13px"),
+                                ("border", &border),
+                                ("text-align", text_align),
+                                ("font-weight", if i == 0 { "600" } else { "normal" }),
+                            ]);
+                            html_tag(tag, cell, &[("style", &cell_style)], true)
+                        })
+                        .collect();
+                    html_tag("tr", &cells, &[], true)
+                })
+                .collect();
+            html_tag(
+                "table",
+                &rows,
+                &[
+                    ("role", "presentation"),
+                    ("cellpadding", "0"),
+                    ("cellspacing", "0"),
+                    ("border", "0"),
+                    ("style", "border-collapse:collapse;margin:0 0 16px;"),
+                ],
+                true,
+            )
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        // Mail clients don't reliably follow links within a message, so
+        // references and footnotes aren't linked to each other
+        ("footnotes", |node, nested, state| {
+            let options = options(state);
+            let theme = &options.theme;
+            let border = format!("1px solid {}", theme.border_color);
+            let list_style = style(&[
+                ("margin", "24px 0 16px"),
+                ("padding", "16px 0 0 32px"),
+                ("border-top", &border),
+                ("font-size", &px(theme.font_size as f64 * 0.875)),
+                ("color", &theme.muted_color),
+            ]);
+            let items: String = footnote_items(node)
+                .iter()
+                .map(|footnote| {
+                    html_tag(
+                        "li",
+                        output_footnote(nested, footnote, state).trim_end(),
+                        &[("style", "margin:0 0 4px;")],
+                        true,
+                    )
+                })
+                .collect();
+            html_tag("ol", &items, &[("style", &list_style)], true)
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            html_tag(
+                "p",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("style", "margin:0 0 16px;")],
+                true,
+            )
+        }),
+        ("link", |node, nested, state| {
+            let href = email_url(node_value(node, "target"), state);
+            let link_style = style(&[
+                ("color", &options(state).theme.link_color),
+                ("text-decoration", "underline"),
+            ]);
+            html_tag(
+                "a",
+                &output_link_content(node, nested, state),
+                &[
+                    ("href", &href),
+                    ("title", node_value(node, "title")),
+                    ("style", &link_style),
+                ],
+                true,
+            )
+        }),
+        ("image", |node, _nested, state| {
+            let src = email_url(node_value(node, "target"), state);
+            html_tag(
+                "img",
+                "",
+                &[
+                    ("src", &src),
+                    ("alt", node_value(node, "alt")),
+                    ("title", node_value(node, "title")),
+                    ("style", "max-width:100%;height:auto;border:0;"),
+                ],
+                false,
+            )
+        }),
+        ("em", |node, nested, state| {
+            html_tag(
+                "em",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("strong", |node, nested, state| {
+            html_tag(
+                "strong",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("u", |node, nested, state| {
+            html_tag(
+                "u",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("del", |node, nested, state| {
+            html_tag(
+                "del",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        // Text in the colour of its background shows when selected
+        ("spoiler", |node, nested, state| {
+            let options = options(state);
+            let color = &options.theme.text_color;
+            let spoiler_style = style(&[("color", color), ("background-color", color)]);
+            html_tag(
+                "span",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("style", &spoiler_style)],
+                true,
+            )
+        }),
+        ("inlineCode", |node, _nested, state| {
+            let options = options(state);
+            let theme = &options.theme;
+            let code_style = style(&[
+                ("padding", "2px 4px"),
+                ("font-family", &theme.code_font_family),
+                ("font-size", &px(theme.font_size as f64 * 0.85)),
+                ("color", &theme.code_color),
+                ("background-color", &theme.code_background),
+                ("border-radius", "3px"),
+            ]);
+            html_tag(
+                "code",
+                &sanitize_text(node_value(node, "content")),
+                &[("style", &code_style)],
+                true,
+            )
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => html_tag(
+                    "sup",
+                    &footnote.number.to_string(),
+                    &[("style", "line-height:0;")],
+                    true,
+                ),
+                None => sanitize_text(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "<br>".to_string()),
+        ("mention", |node, _nested, state| {
+            let mention_style = style(&[
+                ("color", &options(state).theme.link_color),
+                ("font-weight", "bold"),
+            ]);
+            html_tag(
+                "span",
+                &sanitize_text(&mention_text(node, state.mentions.as_deref())),
+                &[("style", &mention_style)],
+                true,
+            )
+        }),
+        ("emoji", |node, _nested, state| {
+            let src = sanitize_url(&emoji_url(node, &state.options.get::<EmojiOptions>()))
+                .unwrap_or_default();
+            let name = emoji_text(node);
+            html_tag(
+                "img",
+                "",
+                &[
+                    ("src", &src),
+                    ("alt", &name),
+                    ("title", &name),
+                    (
+                        "style",
+                        "height:1.375em;width:auto;vertical-align:middle;border:0;",
+                    ),
+                ],
+                false,
+            )
+        }),
+        ("text", |node, _nested, _state| {
+            sanitize_text(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Outputs `ast` as HTML for email bodies: styles are inlined as `style`
+/// attributes instead of classes, code blocks are laid out with tables and
+/// link and image targets are made absolute.
+///
+/// The result is wrapped in a `div` setting the font and colour of the text.
+/// Takes its [`EmailOptions`] and [`EmojiOptions`] from `state`.
+pub fn to_email_html(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let state = state.unwrap_or_default();
+    let options = options(&state);
+    let result = output_for(create_default_rules(), "email")(ast, Some(state));
+    let theme = &options.theme;
+    let body_style = style(&[
+        ("font-family", &theme.font_family),
+        ("font-size", &px(theme.font_size as f64)),
+        ("line-height", "1.5"),
+        ("color", &theme.text_color),
+    ]);
+    html_tag(
+        "div",
+        EXTRA_NEWLINES_R.replace_all(&result, "\n\n").trim(),
+        &[("style", &body_style)],
+        true,
+    )
+}
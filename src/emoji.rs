@@ -0,0 +1,61 @@
+use crate::default_rules::ASTNode;
+use crate::output::node_value;
+
+/// Where the HTML outputs, `to_vdom` and `to_pandoc_json` load the images of
+/// custom emoji from, as set in the [`OutputOptions`](crate::OutputOptions)
+/// of their state.
+#[derive(Debug, Clone)]
+pub struct EmojiOptions {
+    /// URL the emoji ID and file extension are appended to, without a
+    /// trailing slash.
+    pub base_url: String,
+    /// Size in pixels the CDN scales the image to. Discord serves powers of
+    /// two from 16 to 4096.
+    pub size: u32,
+}
+
+impl Default for EmojiOptions {
+    fn default() -> Self {
+        EmojiOptions {
+            base_url: "https://cdn.discordapp.com/emojis".to_string(),
+            size: 48,
+        }
+    }
+}
+
+/// Returns the image URL of an `emoji` node, e.g.
+/// `https://cdn.discordapp.com/emojis/123.gif?size=48` for an animated emoji.
+pub fn emoji_url(node: &ASTNode, options: &EmojiOptions) -> String {
+    let extension = if node_value(node, "animated") == "true" {
+        "gif"
+    } else {
+        "png"
+    };
+    format!(
+        "{}/{}.{}?size={}",
+        options.base_url,
+        node_value(node, "id"),
+        extension,
+        options.size
+    )
+}
+
+/// Text an `emoji` node falls back to where there are no images, e.g. `:blob:`.
+pub(crate) fn emoji_text(node: &ASTNode) -> String {
+    format!(":{}:", node_value(node, "name"))
+}
+
+/// Source of an `emoji` node, as the `emoji` rule parses it.
+pub(crate) fn emoji_source(node: &ASTNode) -> String {
+    let prefix = if node_value(node, "animated") == "true" {
+        "a"
+    } else {
+        ""
+    };
+    format!(
+        "<{}:{}:{}>",
+        prefix,
+        node_value(node, "name"),
+        node_value(node, "id")
+    )
+}
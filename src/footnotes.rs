@@ -0,0 +1,224 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::output::{
+    list_items, node_value, output_block, table_cells, table_header, NestedOutput, OutputState,
+};
+use crate::parse::NestedParser;
+
+/// A footnote of a `footnotes` node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footnote {
+    /// Label of the definition, lowercased.
+    pub label: String,
+    /// Number of the footnote, counted from 1 in order of first reference.
+    pub number: usize,
+    /// Content of the definition, as markdown source. Paragraphs are
+    /// separated by blank lines.
+    pub content: String,
+    /// How many times the footnote is referenced outside of link text. Each of
+    /// these references gets its own back-reference; references in link text
+    /// aren't links, so nothing links back to them.
+    pub references: usize,
+}
+
+/// Collects the labels of footnote references, in the order they are output.
+struct ReferenceFinder {
+    nested: NestedParser,
+    /// Label of each reference, and whether it is output as a link, which
+    /// references in link text aren't.
+    labels: Vec<(String, bool)>,
+}
+
+impl ReferenceFinder {
+    /// Parses `source` like `output_for` does, which parses in list mode
+    /// inside list items.
+    fn find(&mut self, source: &str, inline: bool, in_list: bool, in_link: bool) {
+        let ast = self.nested.parse(source, inline, in_list, in_link);
+        self.nodes(&ast, in_list, in_link);
+    }
+
+    fn nodes(&mut self, ast: &[ASTNode], in_list: bool, in_link: bool) {
+        for node in ast {
+            let content = node_value(node, "content");
+            match node_value(node, "type") {
+                "footnoteRef" => self
+                    .labels
+                    .push((node_value(node, "label").to_string(), !in_link)),
+                "footnoteDef" | "text" | "inlineCode" | "codeBlock" => {}
+                "blockQuote" => self.find(content, false, in_list, false),
+                "list" => {
+                    for item in list_items(node) {
+                        if item.contains("\n\n") {
+                            self.find(&format!("{}\n\n", item), false, true, false);
+                        } else {
+                            self.find(&item, true, true, false);
+                        }
+                    }
+                }
+                "table" => {
+                    let cells = table_cells(node);
+                    for cell in table_header(node).iter().chain(cells.iter().flatten()) {
+                        self.find(cell, true, in_list, false);
+                    }
+                }
+                // Urls in link content aren't autolinked again
+                "link" => self.find(content, true, in_list, true),
+                _ if node.contains_key("content") => self.find(content, true, in_list, in_link),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Numbers the footnotes referenced in `ast` in order of first reference, and
+/// moves their definitions into a `footnotes` node at the end of `ast`, which
+/// outputs the footnote section.
+///
+/// Footnotes referenced only from other definitions are numbered after those
+/// referenced from the document. Definitions nothing references are dropped,
+/// and references without a definition are output as written.
+pub fn collect_footnotes(ast: &mut Vec<ASTNode>) {
+    let mut definitions: HashMap<String, String> = HashMap::new();
+    for node in ast
+        .iter()
+        .filter(|node| node_value(node, "type") == "footnoteDef")
+    {
+        // Like link definitions, the first definition of a label wins
+        definitions
+            .entry(node_value(node, "label").to_string())
+            .or_insert_with(|| node_value(node, "content").to_string());
+    }
+    ast.retain(|node| node_value(node, "type") != "footnoteDef");
+
+    let mut finder = ReferenceFinder {
+        nested: NestedParser::new(create_default_rules()),
+        labels: Vec::new(),
+    };
+    finder.nodes(ast, false, false);
+    let mut footnotes: Vec<Footnote> = Vec::new();
+    let mut next = 0;
+    while next < finder.labels.len() {
+        let (label, linked) = finder.labels[next].clone();
+        next += 1;
+        if let Some(footnote) = footnotes
+            .iter_mut()
+            .find(|footnote| footnote.label == label)
+        {
+            footnote.references += linked as usize;
+        } else if let Some(content) = definitions.get(&label) {
+            footnotes.push(Footnote {
+                label,
+                number: footnotes.len() + 1,
+                content: content.clone(),
+                references: linked as usize,
+            });
+            // The section outputs definitions in order, after the document
+            finder.find(&format!("{}\n\n", content), false, false, false);
+        }
+    }
+
+    if !footnotes.is_empty() {
+        let items: Vec<Value> = footnotes
+            .iter()
+            .map(|footnote| {
+                json!({
+                    "label": footnote.label,
+                    "number": footnote.number,
+                    "content": footnote.content,
+                    "references": footnote.references,
+                })
+            })
+            .collect();
+        ast.push(ASTNode::from([
+            ("type".to_string(), "footnotes".to_string()),
+            (
+                "footnotes".to_string(),
+                serde_json::to_string(&items).unwrap_or_default(),
+            ),
+        ]));
+    }
+}
+
+/// Returns the footnotes of a `footnotes` node, in order.
+pub fn footnote_items(node: &ASTNode) -> Vec<Footnote> {
+    let items: Vec<Value> = node
+        .get("footnotes")
+        .and_then(|items| serde_json::from_str(items).ok())
+        .unwrap_or_default();
+    items
+        .iter()
+        .map(|item| Footnote {
+            label: item["label"].as_str().unwrap_or("").to_string(),
+            number: item["number"].as_u64().unwrap_or(0) as usize,
+            content: item["content"].as_str().unwrap_or("").to_string(),
+            references: item["references"].as_u64().unwrap_or(1) as usize,
+        })
+        .collect()
+}
+
+/// Looks up the footnote a `footnoteRef` node refers to and counts the
+/// reference, returning the footnote and which reference to it this is, from
+/// 1. Returns `None` if the AST being output has no such footnote.
+pub(crate) fn next_reference(node: &ASTNode, state: &mut OutputState) -> Option<(Footnote, usize)> {
+    let label = node_value(node, "label");
+    let footnote = state.footnotes.get(label)?.clone();
+    let count = state
+        .footnote_references
+        .entry(label.to_string())
+        .or_insert(0);
+    // References in link text aren't links, so they have no back-reference
+    if !state.in_link {
+        *count += 1;
+    }
+    Some((footnote, *count))
+}
+
+/// Source of a `footnoteRef` node, output for references without a footnote.
+pub(crate) fn reference_source(node: &ASTNode) -> String {
+    format!("[^{}]", node_value(node, "label"))
+}
+
+/// Id of the footnote numbered `number` in the HTML outputs.
+pub(crate) fn footnote_id(number: usize) -> String {
+    format!("fn-{}", number)
+}
+
+/// Id of a reference to the footnote numbered `number`, for back-references.
+pub(crate) fn reference_id(number: usize, reference: usize) -> String {
+    if reference == 1 {
+        format!("fnref-{}", number)
+    } else {
+        format!("fnref-{}-{}", number, reference)
+    }
+}
+
+/// Outputs the content of a footnote as block content.
+pub(crate) fn output_footnote(
+    nested: &NestedOutput,
+    footnote: &Footnote,
+    state: &mut OutputState,
+) -> String {
+    output_block(nested, &format!("{}\n\n", footnote.content), state)
+}
+
+/// Outputs the footnotes of a `footnotes` node as paragraphs starting with
+/// `marker` of their number, e.g. `[1] Text`, for formats without footnotes.
+pub(crate) fn footnote_paragraphs(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    state: &mut OutputState,
+    marker: fn(usize) -> String,
+) -> String {
+    let mut result = "\n\n".to_string();
+    for footnote in footnote_items(node) {
+        let content = output_footnote(nested, &footnote, state);
+        result.push_str(&format!(
+            "{} {}\n\n",
+            marker(footnote.number),
+            content.trim()
+        ));
+    }
+    result
+}
@@ -0,0 +1,408 @@
+use std::fmt;
+
+/// Kind of token a highlighted span of code is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    /// Built-in constants such as `true` or `None`.
+    Literal,
+    String,
+    Number,
+    Comment,
+}
+
+impl TokenKind {
+    /// Name of the kind, as used in the `hl-*` class names of the HTML output.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Literal => "literal",
+            TokenKind::String => "string",
+            TokenKind::Number => "number",
+            TokenKind::Comment => "comment",
+        }
+    }
+}
+
+/// A span of highlighted code. Spans without a kind are plain code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightSpan {
+    pub text: String,
+    pub kind: Option<TokenKind>,
+}
+
+/// Splits code blocks into highlighted spans, for the `html` and `terminal`
+/// outputs. The text of the returned spans must add up to `code`.
+pub trait Highlighter {
+    fn highlight(&self, lang: &str, code: &str) -> Vec<HighlightSpan>;
+}
+
+impl fmt::Debug for dyn Highlighter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Highlighter")
+    }
+}
+
+struct Language {
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    case_insensitive: bool,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+            "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        literals: &["true", "false", "None", "Some", "Ok", "Err"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["javascript", "js", "jsx", "mjs", "typescript", "ts", "tsx"],
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "let",
+            "new",
+            "of",
+            "return",
+            "static",
+            "switch",
+            "this",
+            "throw",
+            "try",
+            "type",
+            "typeof",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        literals: &["true", "false", "null", "undefined", "NaN", "Infinity"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["go", "golang"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "type",
+            "var",
+        ],
+        literals: &["true", "false", "nil", "iota"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '`'],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "cc", "hpp", "java", "cs", "csharp"],
+        keywords: &[
+            "abstract",
+            "auto",
+            "break",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "extends",
+            "extern",
+            "final",
+            "float",
+            "for",
+            "goto",
+            "if",
+            "implements",
+            "import",
+            "int",
+            "long",
+            "namespace",
+            "new",
+            "package",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "short",
+            "signed",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "throw",
+            "try",
+            "typedef",
+            "union",
+            "unsigned",
+            "using",
+            "virtual",
+            "void",
+            "volatile",
+            "while",
+        ],
+        literals: &["true", "false", "null", "nullptr", "NULL"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["json"],
+        keywords: &[],
+        literals: &["true", "false", "null"],
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["sh", "bash", "shell", "zsh", "console"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        case_insensitive: false,
+    },
+    Language {
+        names: &["sql"],
+        keywords: &[
+            "and", "as", "by", "create", "delete", "desc", "distinct", "drop", "from", "group",
+            "having", "in", "insert", "into", "is", "join", "left", "limit", "not", "on", "or",
+            "order", "right", "select", "set", "table", "update", "values", "where",
+        ],
+        literals: &["null", "true", "false"],
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['\''],
+        case_insensitive: true,
+    },
+];
+
+/// A keyword-based highlighter for common languages that works offline.
+///
+/// Code in other languages is returned as a single plain span.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuiltinHighlighter;
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the byte length of the string starting with `quote` at the start
+/// of `rest`, or `None` if it isn't closed. Only backtick strings span lines.
+fn string_len(rest: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Some(i + c.len_utf8());
+        } else if c == '\n' && quote != '`' {
+            return None;
+        }
+    }
+    None
+}
+
+/// Returns the byte length of a char literal like `'a'` or `'\n'`.
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, c) = chars.next()?;
+    if c == '\\' {
+        chars.next()?;
+    }
+    match chars.next() {
+        Some((i, '\'')) => Some(i + 1),
+        _ => None,
+    }
+}
+
+impl BuiltinHighlighter {
+    fn token(language: &Language, rest: &str) -> Option<(usize, TokenKind)> {
+        if language
+            .line_comments
+            .iter()
+            .any(|comment| rest.starts_with(comment))
+        {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            return Some((len, TokenKind::Comment));
+        }
+        if let Some((open, close)) = language.block_comment {
+            if let Some(comment) = rest.strip_prefix(open) {
+                let len = comment
+                    .find(close)
+                    .map_or(rest.len(), |end| open.len() + end + close.len());
+                return Some((len, TokenKind::Comment));
+            }
+        }
+
+        let first = rest.chars().next()?;
+        if language.quotes.contains(&first) {
+            return string_len(rest, first).map(|len| (len, TokenKind::String));
+        }
+        if first == '\'' {
+            return char_literal_len(rest).map(|len| (len, TokenKind::String));
+        }
+        if first.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(is_identifier_char(c) || c == '.'))
+                .unwrap_or(rest.len());
+            return Some((len, TokenKind::Number));
+        }
+        None
+    }
+}
+
+impl Highlighter for BuiltinHighlighter {
+    fn highlight(&self, lang: &str, code: &str) -> Vec<HighlightSpan> {
+        let lang = lang.to_lowercase();
+        let language = match LANGUAGES
+            .iter()
+            .find(|language| language.names.contains(&lang.as_str()))
+        {
+            Some(language) => language,
+            None => {
+                return vec![HighlightSpan {
+                    text: code.to_string(),
+                    kind: None,
+                }]
+            }
+        };
+
+        let mut spans: Vec<HighlightSpan> = Vec::new();
+        let mut push = |text: &str, kind: Option<TokenKind>| match spans.last_mut() {
+            Some(last) if kind.is_none() && last.kind.is_none() => last.text.push_str(text),
+            _ => spans.push(HighlightSpan {
+                text: text.to_string(),
+                kind,
+            }),
+        };
+
+        let mut i = 0;
+        while i < code.len() {
+            let rest = &code[i..];
+            let first = rest.chars().next().unwrap_or_default();
+
+            if first.is_alphabetic() || first == '_' {
+                let len = rest
+                    .find(|c: char| !is_identifier_char(c))
+                    .unwrap_or(rest.len());
+                let word = &rest[..len];
+                let matches = |words: &[&str]| {
+                    if language.case_insensitive {
+                        words.iter().any(|w| w.eq_ignore_ascii_case(word))
+                    } else {
+                        words.contains(&word)
+                    }
+                };
+                let kind = if matches(language.keywords) {
+                    Some(TokenKind::Keyword)
+                } else if matches(language.literals) {
+                    Some(TokenKind::Literal)
+                } else {
+                    None
+                };
+                push(word, kind);
+                i += len;
+                continue;
+            }
+
+            match BuiltinHighlighter::token(language, rest) {
+                Some((len, kind)) => {
+                    push(&rest[..len], Some(kind));
+                    i += len;
+                }
+                None => {
+                    push(&rest[..first.len_utf8()], None);
+                    i += first.len_utf8();
+                }
+            }
+        }
+        spans
+    }
+}
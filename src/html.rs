@@ -0,0 +1,422 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::{emoji_text, emoji_url, EmojiOptions};
+use crate::footnotes::{
+    collect_footnotes, footnote_id, footnote_items, next_reference, output_footnote, reference_id,
+    reference_source,
+};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, table_align, table_cells, table_header, OutputFunction,
+    OutputState,
+};
+use crate::parse::parser_for;
+use crate::slug::add_heading_ids;
+
+lazy_static::lazy_static! {
+    static ref UNSAFE_URL_R: Regex = Regex::new(r"(?i)^\s*(?:javascript|vbscript|data(?!:image)):").unwrap();
+}
+
+/// Escapes text for use in HTML content and attribute values.
+pub fn sanitize_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' => result.push_str("&amp;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#x27;"),
+            '/' => result.push_str("&#x2F;"),
+            '`' => result.push_str("&#96;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns `None` for urls with a scheme that can run script.
+pub fn sanitize_url(url: &str) -> Option<String> {
+    if UNSAFE_URL_R.is_match(url).unwrap_or(true) {
+        None
+    } else {
+        Some(url.to_string())
+    }
+}
+
+/// Builds an HTML tag, skipping attributes with empty values.
+pub fn html_tag(
+    tag_name: &str,
+    content: &str,
+    attributes: &[(&str, &str)],
+    is_closed: bool,
+) -> String {
+    let mut attribute_string = String::new();
+    for (name, value) in attributes {
+        if !value.is_empty() {
+            attribute_string.push_str(&format!(" {}=\"{}\"", name, sanitize_text(value)));
+        }
+    }
+
+    let unclosed_tag = format!("<{}{}>", tag_name, attribute_string);
+    if is_closed {
+        format!("{}{}</{}>", unclosed_tag, content, tag_name)
+    } else {
+        unclosed_tag
+    }
+}
+
+/// Outputs the content of a `codeBlock` node, with `hl-*` spans if there is
+/// a highlighter and a language.
+fn highlighted_code(node: &ASTNode, state: &OutputState) -> String {
+    let content = node_value(node, "content");
+    let lang = node_value(node, "lang");
+    match &state.highlighter {
+        Some(highlighter) if !lang.is_empty() => highlighter
+            .highlight(lang, content)
+            .iter()
+            .map(|span| match span.kind {
+                Some(kind) => html_tag(
+                    "span",
+                    &sanitize_text(&span.text),
+                    &[("class", &format!("hl-{}", kind.name()))],
+                    true,
+                ),
+                None => sanitize_text(&span.text),
+            })
+            .collect(),
+        _ => sanitize_text(content),
+    }
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            html_tag(
+                &format!("h{}", node_value(node, "level")),
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("id", node_value(node, "id"))],
+                true,
+            )
+        }),
+        ("hr", |_node, _nested, _state| "<hr>".to_string()),
+        ("codeBlock", |node, _nested, state| {
+            let class_name = if node_value(node, "lang").is_empty() {
+                String::new()
+            } else {
+                format!("markdown-code-{}", node_value(node, "lang"))
+            };
+            let code_block = html_tag(
+                "code",
+                &highlighted_code(node, state),
+                &[("class", &class_name)],
+                true,
+            );
+            html_tag("pre", &code_block, &[], true)
+        }),
+        ("blockQuote", |node, nested, state| {
+            html_tag(
+                "blockquote",
+                &output_block(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("list", |node, nested, state| {
+            let checked = list_checked(node);
+            let items: String = list_items(node)
+                .iter()
+                .zip(&checked)
+                .map(|(item, checked)| {
+                    let content = output_list_item(node, nested, item, state);
+                    match checked {
+                        Some(checked) => {
+                            let checkbox = html_tag(
+                                "input",
+                                "",
+                                &[
+                                    ("type", "checkbox"),
+                                    ("checked", if *checked { "checked" } else { "" }),
+                                    ("disabled", "disabled"),
+                                ],
+                                false,
+                            );
+                            html_tag(
+                                "li",
+                                &format!("{} {}", checkbox, content),
+                                &[("class", "task-list-item")],
+                                true,
+                            )
+                        }
+                        None => html_tag("li", &content, &[], true),
+                    }
+                })
+                .collect();
+            let class_name = if checked.iter().any(Option::is_some) {
+                "contains-task-list"
+            } else {
+                ""
+            };
+            if node_value(node, "ordered") == "true" {
+                html_tag(
+                    "ol",
+                    &items,
+                    &[("start", node_value(node, "start")), ("class", class_name)],
+                    true,
+                )
+            } else {
+                html_tag("ul", &items, &[("class", class_name)], true)
+            }
+        }),
+        ("table", |node, nested, state| {
+            let align = table_align(node);
+            let style = |column: usize| match align.get(column).map(String::as_str) {
+                Some("") | None => String::new(),
+                Some(align) => format!("text-align:{};", align),
+            };
+            let header: String = table_header(node)
+                .iter()
+                .enumerate()
+                .map(|(column, cell)| {
+                    html_tag(
+                        "th",
+                        &output_inline(nested, cell, state),
+                        &[("style", &style(column)), ("scope", "col")],
+                        true,
+                    )
+                })
+                .collect();
+            let rows: String = table_cells(node)
+                .iter()
+                .map(|row| {
+                    let cells: String = row
+                        .iter()
+                        .enumerate()
+                        .map(|(column, cell)| {
+                            html_tag(
+                                "td",
+                                &output_inline(nested, cell, state),
+                                &[("style", &style(column))],
+                                true,
+                            )
+                        })
+                        .collect();
+                    html_tag("tr", &cells, &[], true)
+                })
+                .collect();
+            let thead = html_tag("thead", &html_tag("tr", &header, &[], true), &[], true);
+            html_tag(
+                "table",
+                &format!("{}{}", thead, html_tag("tbody", &rows, &[], true)),
+                &[],
+                true,
+            )
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            let items: String = footnote_items(node)
+                .iter()
+                .map(|footnote| {
+                    let content = output_footnote(nested, footnote, state);
+                    html_tag(
+                        "li",
+                        &append_to_last_block(
+                            &content,
+                            &back_references(footnote.number, footnote.references),
+                        ),
+                        &[("id", &footnote_id(footnote.number))],
+                        true,
+                    )
+                })
+                .collect();
+            html_tag(
+                "section",
+                &html_tag("ol", &items, &[], true),
+                &[("class", "footnotes")],
+                true,
+            )
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            html_tag(
+                "div",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("class", "paragraph")],
+                true,
+            )
+        }),
+        ("link", |node, nested, state| {
+            let href = sanitize_url(node_value(node, "target")).unwrap_or_default();
+            html_tag(
+                "a",
+                &output_link_content(node, nested, state),
+                &[("href", &href), ("title", node_value(node, "title"))],
+                true,
+            )
+        }),
+        ("image", |node, _nested, _state| {
+            let src = sanitize_url(node_value(node, "target")).unwrap_or_default();
+            html_tag(
+                "img",
+                "",
+                &[
+                    ("src", &src),
+                    ("alt", node_value(node, "alt")),
+                    ("title", node_value(node, "title")),
+                ],
+                false,
+            )
+        }),
+        ("em", |node, nested, state| {
+            html_tag(
+                "em",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("strong", |node, nested, state| {
+            html_tag(
+                "strong",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("u", |node, nested, state| {
+            html_tag(
+                "u",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("del", |node, nested, state| {
+            html_tag(
+                "del",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("spoiler", |node, nested, state| {
+            html_tag(
+                "span",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[("class", "spoiler")],
+                true,
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            html_tag(
+                "code",
+                &sanitize_text(node_value(node, "content")),
+                &[],
+                true,
+            )
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                // Links can't be nested, so references in link text only show the number
+                Some((footnote, _)) if state.in_link => {
+                    html_tag("sup", &footnote.number.to_string(), &[], true)
+                }
+                Some((footnote, reference)) => {
+                    let link = html_tag(
+                        "a",
+                        &footnote.number.to_string(),
+                        &[
+                            ("href", &format!("#{}", footnote_id(footnote.number))),
+                            ("id", &reference_id(footnote.number, reference)),
+                            ("class", "footnote-ref"),
+                        ],
+                        true,
+                    );
+                    html_tag("sup", &link, &[], true)
+                }
+                None => sanitize_text(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "<br>".to_string()),
+        ("mention", |node, _nested, state| {
+            let kind = format!("mention mention-{}", node_value(node, "kind"));
+            html_tag(
+                "span",
+                &sanitize_text(&mention_text(node, state.mentions.as_deref())),
+                &[("class", &kind), ("data-id", node_value(node, "id"))],
+                true,
+            )
+        }),
+        ("emoji", |node, _nested, state| {
+            let src = sanitize_url(&emoji_url(node, &state.options.get::<EmojiOptions>()))
+                .unwrap_or_default();
+            let name = emoji_text(node);
+            html_tag(
+                "img",
+                "",
+                &[
+                    ("class", "emoji"),
+                    ("src", &src),
+                    ("alt", &name),
+                    ("title", &name),
+                ],
+                false,
+            )
+        }),
+        ("text", |node, _nested, _state| {
+            sanitize_text(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Links back to each reference to the footnote numbered `number`. Links after
+/// the first are numbered, as on GitHub.
+pub(crate) fn back_references(number: usize, references: usize) -> String {
+    (1..=references)
+        .map(|reference| {
+            let content = if reference == 1 {
+                "↩".to_string()
+            } else {
+                format!("↩<sup>{}</sup>", reference)
+            };
+            html_tag(
+                "a",
+                &content,
+                &[
+                    ("href", &format!("#{}", reference_id(number, reference))),
+                    ("class", "footnote-backref"),
+                    ("aria-label", "Back to reference"),
+                ],
+                true,
+            )
+        })
+        .map(|link| format!(" {}", link))
+        .collect()
+}
+
+/// Appends `extra` inside the last element of `html` if it is a paragraph, so
+/// back-references follow the text of a footnote.
+pub(crate) fn append_to_last_block(html: &str, extra: &str) -> String {
+    let html = html.trim_end();
+    match html
+        .strip_suffix("</div>")
+        .or_else(|| html.strip_suffix("</p>"))
+    {
+        Some(rest) => format!("{}{}{}", rest, extra, &html[rest.len()..]),
+        None => format!("{}{}", html, extra),
+    }
+}
+
+/// Parses `source` with the default rules and outputs it as HTML. Headings get
+/// their GitHub-style slug as `id`, and footnotes are collected into a section
+/// at the end.
+pub fn markdown_to_html(source: &str, state: Option<OutputState>) -> String {
+    let rules = create_default_rules();
+    let mut ast = parser_for(rules.clone())(source, None);
+    add_heading_ids(&mut ast);
+    collect_footnotes(&mut ast);
+    output_for(rules, "html")(&ast, state)
+}
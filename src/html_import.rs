@@ -0,0 +1,512 @@
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::markdown::{
+    code_span, escape_block_start, escape_markdown, link_destination, to_markdown,
+};
+use crate::parse::parser_for;
+
+/// Elements that never have content or an end tag.
+const VOID_TAGS: [&str; 9] = [
+    "br", "hr", "img", "input", "meta", "link", "wbr", "area", "source",
+];
+
+/// Elements whose content is dropped, as it isn't shown to the reader.
+const HIDDEN_TAGS: [&str; 6] = ["head", "script", "style", "title", "template", "noscript"];
+
+/// Elements that only group blocks, so their children are imported in their place.
+const CONTAINER_TAGS: [&str; 12] = [
+    "html", "body", "div", "section", "article", "main", "header", "footer", "nav", "aside",
+    "figure", "center",
+];
+
+const BLOCK_TAGS: [&str; 12] = [
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "p",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "hr",
+];
+
+/// An element being built by `parse_html`: its tag, attributes and children.
+type OpenElement = (String, Vec<(String, String)>, Vec<HtmlNode>);
+
+#[derive(Debug, Clone)]
+enum HtmlNode {
+    Text(String),
+    Element {
+        tag: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<HtmlNode>,
+    },
+}
+
+impl HtmlNode {
+    fn attribute(&self, name: &str) -> &str {
+        match self {
+            HtmlNode::Element { attributes, .. } => attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map_or("", |(_, value)| value.as_str()),
+            HtmlNode::Text(_) => "",
+        }
+    }
+
+    fn has_attribute(&self, name: &str) -> bool {
+        match self {
+            HtmlNode::Element { attributes, .. } => attributes.iter().any(|(key, _)| key == name),
+            HtmlNode::Text(_) => false,
+        }
+    }
+
+    fn children(&self) -> &[HtmlNode] {
+        match self {
+            HtmlNode::Element { children, .. } => children,
+            HtmlNode::Text(_) => &[],
+        }
+    }
+
+    fn text_content(&self) -> String {
+        match self {
+            HtmlNode::Text(text) => text.clone(),
+            HtmlNode::Element { tag, .. } if tag == "br" => "\n".to_string(),
+            HtmlNode::Element { children, .. } => {
+                children.iter().map(HtmlNode::text_content).collect()
+            }
+        }
+    }
+}
+
+/// Decodes character references like `&amp;` or `&#x27;`. Unknown ones are
+/// left as they are.
+fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let name = &rest[1..end];
+            let c = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match name.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            c.map(|c| (c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                result.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn parse_attributes(source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = rest[..name_len].to_lowercase();
+        rest = rest[name_len..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = after[1..].find(quote).map_or(after.len(), |end| end + 1);
+                    (&after[1..end], after.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = remaining.trim_start();
+        }
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+    }
+    attributes
+}
+
+/// Builds a tree from `html`, forgiving unclosed and stray tags the way
+/// browsers do for the common cases.
+fn parse_html(html: &str) -> Vec<HtmlNode> {
+    // Open elements, with the root at the bottom
+    let mut stack: Vec<OpenElement> = vec![(String::new(), Vec::new(), Vec::new())];
+
+    fn close(stack: &mut Vec<OpenElement>) {
+        if let Some((tag, attributes, children)) = stack.pop() {
+            if let Some(parent) = stack.last_mut() {
+                parent.2.push(HtmlNode::Element {
+                    tag,
+                    attributes,
+                    children,
+                });
+            }
+        }
+    }
+
+    let is_open =
+        |stack: &[OpenElement], tag: &str| stack.iter().skip(1).any(|(open, _, _)| open == tag);
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        // Only a `<` followed by a tag name, `/`, `!` or `?` starts a tag
+        let text_len = rest
+            .match_indices('<')
+            .map(|(i, _)| i)
+            .find(|&i| {
+                rest[i + 1..]
+                    .starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'))
+            })
+            .unwrap_or(rest.len());
+        if text_len > 0 {
+            let text = decode_entities(&rest[..text_len]);
+            if let Some(open) = stack.last_mut() {
+                open.2.push(HtmlNode::Text(text));
+            }
+            rest = &rest[text_len..];
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            if let Some(open) = stack.last_mut() {
+                open.2.push(HtmlNode::Text(rest.to_string()));
+            }
+            break;
+        };
+        let tag_source = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag_source.starts_with(['!', '?']) {
+            continue;
+        }
+
+        if let Some(name) = tag_source.strip_prefix('/') {
+            let name = name.trim().to_lowercase();
+            if is_open(&stack, &name) {
+                while stack.last().is_some_and(|(open, _, _)| *open != name) {
+                    close(&mut stack);
+                }
+                close(&mut stack);
+            }
+            continue;
+        }
+
+        let tag_source = tag_source.trim_end_matches('/');
+        let name_len = tag_source
+            .find(char::is_whitespace)
+            .unwrap_or(tag_source.len());
+        let name = tag_source[..name_len].to_lowercase();
+        let attributes = parse_attributes(&tag_source[name_len..]);
+
+        // A new block or item ends an open paragraph or item
+        let implied_end = |open: &str| {
+            (open == "p" && (BLOCK_TAGS.contains(&name.as_str()) || name == "li"))
+                || (open == "li" && name == "li")
+        };
+        if stack
+            .last()
+            .is_some_and(|(open, _, _)| implied_end(open.as_str()))
+        {
+            close(&mut stack);
+        }
+
+        if VOID_TAGS.contains(&name.as_str()) {
+            if let Some(open) = stack.last_mut() {
+                open.2.push(HtmlNode::Element {
+                    tag: name,
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+        } else {
+            stack.push((name, attributes, Vec::new()));
+        }
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack
+        .pop()
+        .map(|(_, _, children)| children)
+        .unwrap_or_default()
+}
+
+/// Wraps inline markdown in `marker`, keeping surrounding whitespace outside
+/// it so that the markers stay next to the text.
+fn wrap(content: &str, marker: &str) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content.to_string();
+    }
+    let leading = &content[..content.len() - content.trim_start().len()];
+    let trailing = &content[content.trim_end().len()..];
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                result.push(' ');
+            }
+            in_space = true;
+        } else {
+            result.push(c);
+            in_space = false;
+        }
+    }
+    result
+}
+
+fn inline_markdown(nodes: &[HtmlNode]) -> String {
+    nodes.iter().map(inline_node).collect()
+}
+
+fn inline_node(node: &HtmlNode) -> String {
+    let (tag, children) = match node {
+        HtmlNode::Text(text) => return escape_markdown(&collapse_whitespace(text)),
+        HtmlNode::Element { tag, children, .. } => (tag.as_str(), children),
+    };
+    let content = || inline_markdown(children);
+    match tag {
+        "em" | "i" => wrap(&content(), "*"),
+        "strong" | "b" => wrap(&content(), "**"),
+        "s" | "del" | "strike" => wrap(&content(), "~~"),
+        // Spoilers as Telegram, Matrix and this crate's HTML output write them
+        "tg-spoiler" => wrap(&content(), "||"),
+        "span"
+            if node.has_attribute("data-mx-spoiler")
+                || node
+                    .attribute("class")
+                    .split_whitespace()
+                    .any(|c| c == "spoiler") =>
+        {
+            wrap(&content(), "||")
+        }
+        "code" | "kbd" | "samp" | "tt" => code_span(&collapse_whitespace(&node.text_content())),
+        "br" => "  \n".to_string(),
+        "a" => {
+            let href = node.attribute("href");
+            if href.is_empty() {
+                content()
+            } else {
+                format!(
+                    "[{}]({})",
+                    content().trim(),
+                    link_destination(href, node.attribute("title"))
+                )
+            }
+        }
+        "img" => format!(
+            "![{}]({})",
+            escape_markdown(node.attribute("alt")),
+            link_destination(node.attribute("src"), node.attribute("title"))
+        ),
+        // Checkboxes of task lists, like GitHub renders them
+        "input" if node.attribute("type").eq_ignore_ascii_case("checkbox") => {
+            if node.has_attribute("checked") {
+                "[x] ".to_string()
+            } else {
+                "[ ] ".to_string()
+            }
+        }
+        tag if HIDDEN_TAGS.contains(&tag) => String::new(),
+        _ => content(),
+    }
+}
+
+fn is_block(node: &HtmlNode) -> bool {
+    match node {
+        HtmlNode::Element { tag, .. } => {
+            BLOCK_TAGS.contains(&tag.as_str())
+                || CONTAINER_TAGS.contains(&tag.as_str())
+                || HIDDEN_TAGS.contains(&tag.as_str())
+                || tag == "li"
+        }
+        HtmlNode::Text(_) => false,
+    }
+}
+
+/// Prints block content as markdown blocks. Runs of inline content between
+/// blocks become paragraphs.
+fn blocks_markdown(nodes: &[HtmlNode]) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut inlines = String::new();
+    let flush = |blocks: &mut Vec<String>, inlines: &mut String| {
+        let paragraph = std::mem::take(inlines);
+        let paragraph = paragraph
+            .split('\n')
+            .map(str::trim_start)
+            .collect::<Vec<_>>()
+            .join("\n");
+        // Line breaks at the end of a paragraph are dropped, as browsers do
+        let paragraph = paragraph.trim_end();
+        if !paragraph.is_empty() {
+            blocks.push(escape_block_start(paragraph));
+        }
+    };
+
+    for node in nodes {
+        if !is_block(node) {
+            inlines.push_str(&inline_node(node));
+            continue;
+        }
+        flush(&mut blocks, &mut inlines);
+        let HtmlNode::Element { tag, children, .. } = node else {
+            continue;
+        };
+        match tag.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level: usize = tag[1..].parse().unwrap_or(1);
+                let content = collapse_whitespace(&inline_markdown(children).replace("  \n", " "));
+                if !content.trim().is_empty() {
+                    blocks.push(format!("{} {}", "#".repeat(level), content.trim()));
+                }
+            }
+            "p" | "li" => {
+                inlines.push_str(&inline_markdown(children));
+                flush(&mut blocks, &mut inlines);
+            }
+            "pre" => blocks.push(code_block_markdown(node)),
+            "blockquote" => {
+                let quoted = blocks_markdown(children)
+                    .join("\n\n")
+                    .split('\n')
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if !quoted.is_empty() {
+                    blocks.push(quoted);
+                }
+            }
+            "ul" | "ol" => {
+                let list = list_markdown(node);
+                if !list.is_empty() {
+                    blocks.push(list);
+                }
+            }
+            "hr" => blocks.push("---".to_string()),
+            tag if HIDDEN_TAGS.contains(&tag) => {}
+            _ => blocks.extend(blocks_markdown(children)),
+        }
+    }
+    flush(&mut blocks, &mut inlines);
+    blocks
+}
+
+fn code_block_markdown(pre: &HtmlNode) -> String {
+    // The language is usually a class like `language-rust` on the inner `code`
+    let code = pre
+        .children()
+        .iter()
+        .find(|child| matches!(child, HtmlNode::Element { tag, .. } if tag == "code"));
+    let lang = [pre, code.unwrap_or(pre)]
+        .iter()
+        .flat_map(|node| node.attribute("class").split_whitespace())
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+        })
+        .unwrap_or("");
+    let content = pre.text_content();
+    let content = content.strip_prefix('\n').unwrap_or(&content);
+    let content = content.trim_end_matches('\n');
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, lang, content, fence)
+}
+
+fn list_markdown(list: &HtmlNode) -> String {
+    let HtmlNode::Element { tag, children, .. } = list else {
+        return String::new();
+    };
+    let ordered = tag == "ol";
+    let start: usize = list.attribute("start").trim().parse().unwrap_or(1);
+    children
+        .iter()
+        .filter(|child| matches!(child, HtmlNode::Element { tag, .. } if tag == "li"))
+        .enumerate()
+        .map(|(i, item)| {
+            let bullet = if ordered {
+                format!("{}.", start + i)
+            } else {
+                "*".to_string()
+            };
+            // Nested lists follow the text of their item on the next line,
+            // like the items the `list` rule produces
+            let tight = item.children().iter().filter(|child| is_block(child)).all(
+                |child| matches!(child, HtmlNode::Element { tag, .. } if tag == "ul" || tag == "ol"),
+            );
+            let separator = if tight { "\n" } else { "\n\n" };
+            let content = blocks_markdown(item.children())
+                .join(separator)
+                .split('\n')
+                .enumerate()
+                .map(|(i, line)| {
+                    if i == 0 || line.is_empty() {
+                        line.to_string()
+                    } else {
+                        format!("{}{}", " ".repeat(bullet.len() + 1), line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("{} {}", bullet, content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Imports `html`, e.g. pasted from a browser, as an AST of the default rules.
+///
+/// Headings, paragraphs, code blocks, quotes, lists, links, images, emphasis,
+/// strong, strikethrough, line breaks and rules are converted. Other elements
+/// are replaced by their content, except hidden ones like `script`, which are
+/// dropped. That includes underline, since the default rules read `__` as
+/// strong.
+pub fn from_html(html: &str) -> Vec<ASTNode> {
+    let source = blocks_markdown(&parse_html(html)).join("\n\n");
+    parser_for(create_default_rules())(&format!("{}\n\n", source), None)
+}
+
+/// Converts `html` to markdown, by importing it with [`from_html`] and
+/// printing it with `to_markdown`.
+pub fn html_to_markdown(html: &str) -> String {
+    to_markdown(&from_html(html), None)
+}
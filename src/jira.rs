@@ -0,0 +1,194 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_paragraphs, next_reference, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, task_marker, OutputFunction,
+    OutputState,
+};
+use crate::plain_text::PlainTextOptions;
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Escapes the characters Atlassian wiki markup uses for formatting.
+pub fn escape_jira(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\*_+-{}[]|!^~?#".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Percent-encodes the characters that end a link or image in a url.
+fn escape_url(url: &str) -> String {
+    let mut result = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '|' | '[' | ']' | '!' | ' ' => result.push_str(&format!("%{:02X}", c as u32)),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn wrap(marker: &str, content: &str) -> String {
+    format!("{}{}{}", marker, content, marker)
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            format!(
+                "h{}. {}",
+                node_value(node, "level"),
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("hr", |_node, _nested, _state| "----\n\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            let lang = node_value(node, "lang");
+            let content = node_value(node, "content");
+            // `{code}` in the content would end the macro early, so it goes in a
+            // `{noformat}` block instead, without the language
+            if content.contains("{code") {
+                return format!("{{noformat}}\n{}\n{{noformat}}\n\n", content);
+            }
+            let open = if lang.is_empty() {
+                "{code}".to_string()
+            } else {
+                format!("{{code:{}}}", lang)
+            };
+            format!("{}\n{}\n{{code}}\n\n", open, content)
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            format!("{{quote}}\n{}\n{{quote}}\n\n", content.trim())
+        }),
+        // Nested lists repeat the markers of their parents, e.g. `#*` or `**`
+        ("list", |node, nested, state| {
+            let marker = |ordered: bool| if ordered { '#' } else { '*' };
+            let mut prefix: String = state.lists.iter().map(|&ordered| marker(ordered)).collect();
+            prefix.push(marker(node_value(node, "ordered") == "true"));
+
+            let mut result = String::new();
+            for (item, checked) in list_items(node).iter().zip(list_checked(node)) {
+                let content = output_list_item(node, nested, item, state);
+                let task = task_marker(checked, "☐ ", "☑ ");
+                result.push_str(&format!("{} {}{}\n", prefix, task, content.trim()));
+            }
+            if state.lists.is_empty() {
+                result.push('\n');
+            }
+            result
+        }),
+        ("table", |node, nested, state| {
+            let mut lines = Vec::new();
+            for (i, row) in output_table_rows(node, nested, state).iter().enumerate() {
+                let separator = if i == 0 { "||" } else { "|" };
+                // Empty cells need a space, or the separators merge
+                let cells: Vec<&str> = row
+                    .iter()
+                    .map(|cell| if cell.is_empty() { " " } else { cell.as_str() })
+                    .collect();
+                lines.push(format!(
+                    "{}{}{}",
+                    separator,
+                    cells.join(separator),
+                    separator
+                ));
+            }
+            format!("{}\n\n", lines.join("\n"))
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_paragraphs(node, nested, state, |number| format!("^{}^", number))
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            let target = escape_url(node_value(node, "target"));
+            if content.is_empty() || content == target {
+                format!("[{}]", target)
+            } else {
+                format!("[{}|{}]", content, target)
+            }
+        }),
+        ("image", |node, _nested, _state| {
+            let alt = node_value(node, "alt");
+            let target = escape_url(node_value(node, "target"));
+            if alt.is_empty() {
+                format!("!{}!", target)
+            } else {
+                format!("!{}|alt={}!", target, escape_jira(alt))
+            }
+        }),
+        ("em", |node, nested, state| {
+            wrap(
+                "_",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("strong", |node, nested, state| {
+            wrap(
+                "*",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("u", |node, nested, state| {
+            wrap(
+                "+",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("del", |node, nested, state| {
+            wrap(
+                "-",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        // Jira has no spoilers, so they are masked as in plain text
+        ("spoiler", |_node, _nested, state| {
+            state.options.get::<PlainTextOptions>().spoiler_mask.clone()
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            format!("{{{{{}}}}}", escape_jira(node_value(node, "content")))
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("^{}^", footnote.number),
+                None => escape_jira(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\\\\\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_jira(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_jira(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_jira(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Converts `ast` to Atlassian wiki markup, as used by Jira and Confluence.
+pub fn to_jira(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "jira")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
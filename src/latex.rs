@@ -0,0 +1,319 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{next_reference, output_footnote, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, table_align, OutputFunction,
+    OutputState,
+};
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Options for `to_latex`.
+#[derive(Debug, Clone)]
+pub struct LatexOptions {
+    /// Output code blocks as `lstlisting` environments instead of `verbatim`.
+    pub listings: bool,
+    /// Wrap the output in a complete `article` document with the packages it needs.
+    pub standalone: bool,
+}
+
+impl Default for LatexOptions {
+    fn default() -> Self {
+        LatexOptions {
+            listings: true,
+            standalone: false,
+        }
+    }
+}
+
+/// Escapes LaTeX special characters in text.
+pub fn escape_latex(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '~' => result.push_str(r"\textasciitilde{}"),
+            '^' => result.push_str(r"\textasciicircum{}"),
+            '\\' => result.push_str(r"\textbackslash{}"),
+            '<' => result.push_str(r"\textless{}"),
+            '>' => result.push_str(r"\textgreater{}"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Escapes the characters `\href` and `\includegraphics` don't accept in urls.
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "/")
+        .replace('%', r"\%")
+        .replace('#', r"\#")
+        .replace('{', "%7B")
+        .replace('}', "%7D")
+}
+
+/// Returns the name `listings` knows the language of a code block by, for
+/// the languages it has a definition for.
+fn listings_language(lang: &str) -> Option<&'static str> {
+    let language = match lang.to_lowercase().as_str() {
+        "ada" => "Ada",
+        "awk" => "Awk",
+        "bash" | "shell" | "zsh" => "bash",
+        "sh" => "sh",
+        "c" | "h" => "C",
+        "cpp" | "c++" | "cc" | "cxx" | "hpp" => "C++",
+        "cobol" => "Cobol",
+        "delphi" => "Delphi",
+        "erlang" | "erl" => "erlang",
+        "fortran" | "f90" => "Fortran",
+        "haskell" | "hs" => "Haskell",
+        "html" | "htm" => "HTML",
+        "java" => "Java",
+        "lisp" | "elisp" => "Lisp",
+        "make" | "makefile" => "make",
+        "matlab" => "Matlab",
+        "ocaml" | "ml" => "ML",
+        "octave" => "Octave",
+        "pascal" => "Pascal",
+        "perl" | "pl" => "Perl",
+        "php" => "PHP",
+        "prolog" => "Prolog",
+        "python" | "py" => "Python",
+        "r" => "R",
+        "ruby" | "rb" => "Ruby",
+        "sql" => "SQL",
+        "tcl" => "tcl",
+        "tex" | "latex" => "TeX",
+        "vbscript" | "vbs" => "VBScript",
+        "verilog" => "Verilog",
+        "vhdl" => "VHDL",
+        "xml" | "svg" => "XML",
+        "xslt" => "XSLT",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Keeps the content of a `name` environment that is typeset verbatim from
+/// ending it early, by splitting any `\end{name}` in it with a space.
+fn guard_end(content: &str, name: &str) -> String {
+    let end = format!("\\end{{{}}}", name);
+    content.replace(&end, &format!("\\end {{{}}}", name))
+}
+
+fn command(name: &str, content: &str) -> String {
+    format!("\\{}{{{}}}", name, content)
+}
+
+fn environment(name: &str, options: &str, content: &str) -> String {
+    format!(
+        "\\begin{{{}}}{}\n{}\n\\end{{{}}}\n",
+        name, options, content, name
+    )
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let name = match node_value(node, "level") {
+                "1" => "section",
+                "2" => "subsection",
+                "3" => "subsubsection",
+                "4" => "paragraph",
+                _ => "subparagraph",
+            };
+            command(
+                name,
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("hr", |_node, _nested, _state| {
+            "\\noindent\\rule{\\linewidth}{0.4pt}\n\n".to_string()
+        }),
+        ("codeBlock", |node, _nested, state| {
+            let content = node_value(node, "content");
+            let lang = node_value(node, "lang");
+            if state.options.get::<LatexOptions>().listings {
+                let options = listings_language(lang).map_or(String::new(), |language| {
+                    format!("[language={{{}}}]", language)
+                });
+                environment("lstlisting", &options, &guard_end(content, "lstlisting")) + "\n"
+            } else {
+                environment("verbatim", "", &guard_end(content, "verbatim")) + "\n"
+            }
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            environment("quote", "", content.trim()) + "\n"
+        }),
+        ("list", |node, nested, state| {
+            let mut items = Vec::new();
+            for (item, checked) in list_items(node).iter().zip(list_checked(node)) {
+                let content = output_list_item(node, nested, item, state);
+                // Task list items replace their label with a box, from amssymb
+                let label = match checked {
+                    Some(true) => "[$\\boxtimes$]",
+                    Some(false) => "[$\\square$]",
+                    None => "",
+                };
+                items.push(format!("  \\item{} {}", label, content.trim()));
+            }
+            let items = items.join("\n");
+
+            let list = if node_value(node, "ordered") == "true" {
+                let start: usize = node_value(node, "start").parse().unwrap_or(1);
+                // Each level of nested enumerate environments has its own counter
+                let depth = state.lists.iter().filter(|&&ordered| ordered).count();
+                let counter = ["enumi", "enumii", "enumiii", "enumiv"][depth.min(3)];
+                let counter = if start > 1 {
+                    format!("  \\setcounter{{{}}}{{{}}}\n", counter, start - 1)
+                } else {
+                    String::new()
+                };
+                environment("enumerate", "", &format!("{}{}", counter, items))
+            } else {
+                environment("itemize", "", &items)
+            };
+            list + "\n"
+        }),
+        ("table", |node, nested, state| {
+            let columns: String = table_align(node)
+                .iter()
+                .map(|align| match align.as_str() {
+                    "center" => 'c',
+                    "right" => 'r',
+                    _ => 'l',
+                })
+                .collect();
+            let rows: Vec<String> = output_table_rows(node, nested, state)
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let cells: Vec<String> = row
+                        .iter()
+                        .map(|cell| {
+                            if i == 0 && !cell.is_empty() {
+                                format!("\\textbf{{{}}}", cell)
+                            } else {
+                                cell.clone()
+                            }
+                        })
+                        .collect();
+                    let line = format!("  {} \\\\", cells.join(" & "));
+                    if i == 0 {
+                        format!("{}\n  \\hline", line)
+                    } else {
+                        line
+                    }
+                })
+                .collect();
+            environment("tabular", &format!("{{{}}}", columns), &rows.join("\n")) + "\n"
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        // Footnotes are typeset at their first reference
+        ("footnotes", |_node, _nested, _state| String::new()),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            format!(
+                "\\href{{{}}}{{{}}}",
+                escape_url(node_value(node, "target")),
+                content
+            )
+        }),
+        ("image", |node, _nested, _state| {
+            command("includegraphics", &escape_url(node_value(node, "target")))
+        }),
+        ("em", |node, nested, state| {
+            command(
+                "emph",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("strong", |node, nested, state| {
+            command(
+                "textbf",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("u", |node, nested, state| {
+            command(
+                "uline",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        ("del", |node, nested, state| {
+            command(
+                "sout",
+                &output_inline(nested, node_value(node, "content"), state),
+            )
+        }),
+        // Printed pages can't hide text, so spoilers keep their content
+        ("spoiler", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            command("texttt", &escape_latex(node_value(node, "content")))
+        }),
+        ("footnoteRef", |node, nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, 1)) => {
+                    command("footnote", output_footnote(nested, &footnote, state).trim())
+                }
+                Some((footnote, _)) => format!("\\footnotemark[{}]", footnote.number),
+                None => escape_latex(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\\\\\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_latex(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_latex(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_latex(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Outputs `ast` as LaTeX. Takes its [`LatexOptions`] from `state`.
+pub fn to_latex(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let state = state.unwrap_or_default();
+    let options = state.options.get::<LatexOptions>();
+    let result = output_for(create_default_rules(), "latex")(ast, Some(state));
+    let body = EXTRA_NEWLINES_R.replace_all(&result, "\n\n");
+    let body = body.trim();
+
+    if options.standalone {
+        let mut packages = String::from("\\usepackage{graphicx}\n\\usepackage{hyperref}\n");
+        // `normalem` keeps `\emph` italic instead of underlined
+        packages.push_str("\\usepackage[normalem]{ulem}\n");
+        if options.listings {
+            packages.push_str("\\usepackage{listings}\n");
+        }
+        // Boxes of task list items
+        if body.contains("$\\square$") || body.contains("$\\boxtimes$") {
+            packages.push_str("\\usepackage{amssymb}\n");
+        }
+        format!(
+            "\\documentclass{{article}}\n{}\\begin{{document}}\n{}\n\\end{{document}}\n",
+            packages, body
+        )
+    } else {
+        format!("{}\n", body)
+    }
+}
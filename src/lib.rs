@@ -1,170 +1,1293 @@
-mod default_rules;
-mod parse;
-
-pub use default_rules::{create_default_rules, ASTNode, Rule, RuleMap, State};
-pub use parse::parser_for;
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn parse_text(input: &str) -> Vec<ASTNode> {
-        let rules = create_default_rules();
-        let parse = parser_for(rules);
-        parse(input, None)
-    }
-
-    #[test]
-    fn test_heading() {
-        let result = parse_text("# Heading 1\n\n");
-        assert_eq!(result[0]["type"], "heading");
-        assert_eq!(result[0]["level"], "1");
-        assert_eq!(result[0]["content"], "Heading 1");
-    }
-
-    #[test]
-    fn test_lheading() {
-        let result = parse_text("Heading 1\n=======\n\n");
-        assert_eq!(result[0]["type"], "heading");
-        assert_eq!(result[0]["level"], "1");
-    }
-
-    #[test]
-    fn test_hr() {
-        let result = parse_text("---\n\n");
-        assert_eq!(result[0]["type"], "hr");
-    }
-
-    #[test]
-    fn test_codeblock() {
-        let result = parse_text("```js\ncode block\n```\n\n");
-        assert_eq!(result[0]["type"], "codeBlock");
-        assert_eq!(result[0]["content"], "code block");
-        assert_eq!(result[0]["lang"], "js");
-    }
-
-    #[test]
-    fn test_fence() {
-        let result = parse_text("~~~js\ncode block\n~~~\n\n");
-        assert_eq!(result[0]["type"], "codeBlock");
-        assert_eq!(result[0]["content"], "code block");
-        assert_eq!(result[0]["lang"], "js");
-    }
-
-    #[test]
-    fn test_blockquote() {
-        let result = parse_text("> quote\n\n");
-        assert_eq!(result[0]["type"], "blockQuote");
-    }
-
-    #[test]
-    fn test_list() {
-        let result = parse_text("* item 1\n* item 2\n\n");
-        assert_eq!(result[0]["type"], "list");
-        assert_eq!(result[0]["ordered"], "false");
-    }
-
-    #[test]
-    fn test_ordered_list() {
-        let result = parse_text("1. item 1\n2. item 2\n\n");
-        assert_eq!(result[0]["type"], "list");
-        assert_eq!(result[0]["ordered"], "true");
-    }
-
-    #[test]
-    fn test_link() {
-        let result = parse_text("[text](https://example.com)");
-        assert_eq!(result[0]["type"], "link");
-        assert_eq!(result[0]["content"], "text");
-        assert_eq!(result[0]["target"], "https://example.com");
-    }
-
-    #[test]
-    fn test_image() {
-        let result = parse_text("![alt](image.jpg)");
-        assert_eq!(result[0]["type"], "image");
-        assert_eq!(result[0]["alt"], "alt");
-        assert_eq!(result[0]["target"], "image.jpg");
-    }
-
-    #[test]
-    fn test_strong() {
-        let result = parse_text("**bold**");
-        assert_eq!(result[0]["type"], "strong");
-        assert_eq!(result[0]["content"], "bold");
-    }
-
-    #[test]
-    fn test_em() {
-        let result = parse_text("*italic*");
-        assert_eq!(result[0]["type"], "em");
-        assert_eq!(result[0]["content"], "italic");
-    }
-
-    #[test]
-    fn test_inline_code() {
-        let result = parse_text("`code`");
-        assert_eq!(result[0]["type"], "inlineCode");
-        assert_eq!(result[0]["content"], "code");
-    }
-
-    #[test]
-    fn test_url() {
-        let result = parse_text("https://example.com");
-        assert_eq!(result[0]["type"], "link");
-        assert_eq!(result[0]["target"], "https://example.com");
-    }
-
-    #[test]
-    fn test_autolink() {
-        let result = parse_text("<https://example.com>");
-        assert_eq!(result[0]["type"], "link");
-        assert_eq!(result[0]["target"], "https://example.com");
-    }
-
-    #[test]
-    fn test_mailto() {
-        let result = parse_text("<user@example.com>");
-        assert_eq!(result[0]["type"], "link");
-        assert_eq!(result[0]["target"], "mailto:user@example.com");
-    }
-
-    #[test]
-    fn test_paragraph() {
-        let result = parse_text("This is a paragraph\n\n");
-        assert_eq!(result[0]["type"], "paragraph");
-        assert!(result[0]["content"].contains("This is a paragraph"));
-    }
-
-    #[test]
-    fn test_escape() {
-        let result = parse_text(r#"\*not italic\*"#);
-        assert_eq!(result[0]["type"], "text");
-        assert_eq!(result[0]["content"], "*");
-    }
-
-    #[test]
-    fn test_del() {
-        let result = parse_text("~~strikethrough~~");
-        assert_eq!(result[0]["type"], "del");
-        assert_eq!(result[0]["content"], "strikethrough");
-    }
-
-    #[test]
-    fn test_multiple_elements() {
-        let result = parse_text("# Heading\n\n**strong**\n_italic_\n\n");
-        println!("{:?}", result);
-        assert_eq!(result[0]["type"], "heading");
-        assert_eq!(result[0]["content"], "Heading");
-        assert_eq!(result[1]["type"], "newline");
-        assert_eq!(result[2]["type"], "newline");
-        assert_eq!(result[3]["type"], "strong");
-        assert_eq!(result[3]["content"], "strong");
-        assert_eq!(result[4]["type"], "newline");
-        assert_eq!(result[5]["type"], "em");
-        assert_eq!(result[5]["content"], "italic");
-        assert_eq!(result[6]["type"], "newline");
-        assert_eq!(result[7]["type"], "newline");
-        assert_eq!(result.len(), 8);
-    }
-}
+mod commonmark;
+mod default_rules;
+mod email;
+mod emoji;
+mod footnotes;
+mod highlight;
+mod html;
+mod html_import;
+mod jira;
+mod latex;
+mod markdown;
+mod matrix;
+mod mdast;
+mod mention;
+mod output;
+mod pandoc;
+mod parse;
+mod plain_text;
+mod roff;
+mod slack;
+mod slug;
+mod task_list;
+mod telegram;
+mod terminal;
+mod toc;
+mod truncate;
+mod vdom;
+
+pub use commonmark::{
+    commonmark_to_html, create_commonmark_rules, parse_spec_examples, run_spec_examples,
+    SpecExample, SpecReport, SpecSection,
+};
+pub use default_rules::{create_default_rules, ASTNode, Rule, RuleMap, State};
+pub use email::{to_email_html, EmailOptions, EmailTheme};
+pub use emoji::{emoji_url, EmojiOptions};
+pub use footnotes::{collect_footnotes, footnote_items, Footnote};
+pub use highlight::{BuiltinHighlighter, HighlightSpan, Highlighter, TokenKind};
+pub use html::{html_tag, markdown_to_html, sanitize_text, sanitize_url};
+pub use html_import::{from_html, html_to_markdown};
+pub use jira::{escape_jira, to_jira};
+pub use latex::{escape_latex, to_latex, LatexOptions};
+pub use markdown::{escape_markdown, to_markdown};
+pub use matrix::{
+    strip_body_reply_fallback, strip_reply_fallback, to_matrix, MatrixMessage, MatrixReply,
+};
+pub use mdast::{from_mdast, to_mdast, MDAST_CUSTOM_PREFIX};
+pub use mention::{mention_kind, MentionKind, MentionResolver};
+pub use output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, table_align, table_cells,
+    table_header, NestedOutput, OutputClosure, OutputFunction, OutputOptions, OutputState,
+};
+pub use pandoc::{to_pandoc_json, PANDOC_API_VERSION};
+pub use parse::parser_for;
+pub use plain_text::{to_plain_text, PlainTextOptions};
+pub use roff::{escape_roff, to_roff, RoffOptions};
+pub use slack::{escape_slack, to_slack};
+pub use slug::{add_heading_ids, github_slug, heading_slugs, Slugger};
+pub use task_list::toggle_task_item;
+pub use telegram::{
+    escape_markdown_v2, escape_telegram_html, to_telegram_html, to_telegram_markdown,
+};
+pub use terminal::{to_terminal, TerminalOptions};
+pub use toc::{table_of_contents, toc_to_list, TocEntry, TocOptions};
+pub use truncate::{truncate, TruncateCount};
+pub use vdom::to_vdom;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_text(input: &str) -> Vec<ASTNode> {
+        let rules = create_default_rules();
+        let parse = parser_for(rules);
+        parse(input, None)
+    }
+
+    #[test]
+    fn test_heading() {
+        let result = parse_text("# Heading 1\n\n");
+        assert_eq!(result[0]["type"], "heading");
+        assert_eq!(result[0]["level"], "1");
+        assert_eq!(result[0]["content"], "Heading 1");
+    }
+
+    #[test]
+    fn test_lheading() {
+        let result = parse_text("Heading 1\n=======\n\n");
+        assert_eq!(result[0]["type"], "heading");
+        assert_eq!(result[0]["level"], "1");
+        assert_eq!(result[0]["content"], "Heading 1");
+        // The underline is consumed with the heading text
+        let result = parse_text("Heading 2\n---\n\nText\n\n");
+        assert_eq!(result[0]["level"], "2");
+        assert_eq!(result[1]["type"], "newline");
+        assert_eq!(result[3]["type"], "paragraph");
+        assert_eq!(result.len(), 6);
+    }
+
+    #[test]
+    fn test_hr() {
+        let result = parse_text("---\n\n");
+        assert_eq!(result[0]["type"], "hr");
+    }
+
+    #[test]
+    fn test_codeblock() {
+        let result = parse_text("```js\ncode block\n```\n\n");
+        assert_eq!(result[0]["type"], "codeBlock");
+        assert_eq!(result[0]["content"], "code block");
+        assert_eq!(result[0]["lang"], "js");
+    }
+
+    #[test]
+    fn test_fence() {
+        let result = parse_text("~~~js\ncode block\n~~~\n\n");
+        assert_eq!(result[0]["type"], "codeBlock");
+        assert_eq!(result[0]["content"], "code block");
+        assert_eq!(result[0]["lang"], "js");
+    }
+
+    #[test]
+    fn test_blockquote() {
+        let result = parse_text("> quote\n\n");
+        assert_eq!(result[0]["type"], "blockQuote");
+    }
+
+    #[test]
+    fn test_list() {
+        let result = parse_text("* item 1\n* item 2\n\n");
+        assert_eq!(result[0]["type"], "list");
+        assert_eq!(result[0]["ordered"], "false");
+    }
+
+    #[test]
+    fn test_ordered_list() {
+        let result = parse_text("1. item 1\n2. item 2\n\n");
+        assert_eq!(result[0]["type"], "list");
+        assert_eq!(result[0]["ordered"], "true");
+    }
+
+    #[test]
+    fn test_link() {
+        let result = parse_text("[text](https://example.com)");
+        assert_eq!(result[0]["type"], "link");
+        assert_eq!(result[0]["content"], "text");
+        assert_eq!(result[0]["target"], "https://example.com");
+    }
+
+    #[test]
+    fn test_image() {
+        let result = parse_text("![alt](image.jpg)");
+        assert_eq!(result[0]["type"], "image");
+        assert_eq!(result[0]["alt"], "alt");
+        assert_eq!(result[0]["target"], "image.jpg");
+    }
+
+    #[test]
+    fn test_strong() {
+        let result = parse_text("**bold**");
+        assert_eq!(result[0]["type"], "strong");
+        assert_eq!(result[0]["content"], "bold");
+        let result = parse_text("__bold__");
+        assert_eq!(result[0]["type"], "strong");
+        assert_eq!(result[0]["content"], "bold");
+    }
+
+    #[test]
+    fn test_em() {
+        let result = parse_text("*italic*");
+        assert_eq!(result[0]["type"], "em");
+        assert_eq!(result[0]["content"], "italic");
+    }
+
+    #[test]
+    fn test_inline_code() {
+        let result = parse_text("`code`");
+        assert_eq!(result[0]["type"], "inlineCode");
+        assert_eq!(result[0]["content"], "code");
+    }
+
+    #[test]
+    fn test_url() {
+        let result = parse_text("https://example.com");
+        assert_eq!(result[0]["type"], "link");
+        assert_eq!(result[0]["target"], "https://example.com");
+    }
+
+    #[test]
+    fn test_autolink() {
+        let result = parse_text("<https://example.com>");
+        assert_eq!(result[0]["type"], "link");
+        assert_eq!(result[0]["target"], "https://example.com");
+    }
+
+    #[test]
+    fn test_mailto() {
+        let result = parse_text("<user@example.com>");
+        assert_eq!(result[0]["type"], "link");
+        assert_eq!(result[0]["target"], "mailto:user@example.com");
+    }
+
+    #[test]
+    fn test_paragraph() {
+        let result = parse_text("This is a paragraph\n\n");
+        assert_eq!(result[0]["type"], "paragraph");
+        assert!(result[0]["content"].contains("This is a paragraph"));
+    }
+
+    #[test]
+    fn test_escape() {
+        let result = parse_text(r#"\*not italic\*"#);
+        assert_eq!(result[0]["type"], "text");
+        assert_eq!(result[0]["content"], "*");
+    }
+
+    #[test]
+    fn test_del() {
+        let result = parse_text("~~strikethrough~~");
+        assert_eq!(result[0]["type"], "del");
+        assert_eq!(result[0]["content"], "strikethrough");
+    }
+
+    #[test]
+    fn test_multiple_elements() {
+        let result = parse_text("# Heading\n\n**strong**\n_italic_\n\n");
+        println!("{:?}", result);
+        assert_eq!(result[0]["type"], "heading");
+        assert_eq!(result[0]["content"], "Heading");
+        assert_eq!(result[1]["type"], "newline");
+        assert_eq!(result[2]["type"], "newline");
+        assert_eq!(result[3]["type"], "strong");
+        assert_eq!(result[3]["content"], "strong");
+        assert_eq!(result[4]["type"], "newline");
+        assert_eq!(result[5]["type"], "em");
+        assert_eq!(result[5]["content"], "italic");
+        assert_eq!(result[6]["type"], "newline");
+        assert_eq!(result[7]["type"], "newline");
+        assert_eq!(result.len(), 8);
+    }
+
+    #[test]
+    fn test_html_output() {
+        let result = markdown_to_html(
+            "# Heading\n\nSome **bold** [link](https://example.com \"Title\")\n\n",
+            None,
+        );
+        assert!(result.contains("<h1 id=\"heading\">Heading</h1>"));
+        assert!(result.contains("<strong>bold</strong>"));
+        assert!(
+            result.contains(r#"<a href="https:&#x2F;&#x2F;example.com" title="Title">link</a>"#)
+        );
+    }
+
+    #[test]
+    fn test_html_list_and_code() {
+        let result = markdown_to_html("* one\n* two\n\n```rs\nlet x = 1 < 2;\n```\n\n", None);
+        assert!(result.contains("<ul><li>one</li><li>two</li></ul>"));
+        assert!(result
+            .contains(r#"<pre><code class="markdown-code-rs">let x = 1 &lt; 2;</code></pre>"#));
+    }
+
+    #[test]
+    fn test_output_for_custom_format() {
+        let mut rules = create_default_rules();
+        rules
+            .get_mut("strong")
+            .unwrap()
+            .output
+            .insert("shout".to_string(), |node, nested, state| {
+                output_inline(nested, &node["content"], state).to_uppercase()
+            });
+        rules
+            .get_mut("text")
+            .unwrap()
+            .output
+            .insert("shout".to_string(), |node, _nested, _state| {
+                node["content"].clone()
+            });
+
+        let ast = parse_text("quiet **loud words** *skipped*");
+        let output = output_for(rules, "shout");
+        assert_eq!(output(&ast, None), "quiet LOUD WORDS ");
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let ast = parse_text(
+            "# Notes\n\nRead **the** [docs](https://example.com) ![logo](logo.png)\n\n* one\n* two\n\n```\ncode\n```\n\n",
+        );
+        let result = to_plain_text(&ast, None);
+        assert_eq!(
+            result,
+            "Notes\n\nRead the docs (https://example.com) logo\n\n- one\n- two\n\ncode"
+        );
+    }
+
+    #[test]
+    fn test_plain_text_options() {
+        let ast = parse_text(
+            "See [docs](https://example.com)\n\n```\ncode\n```\n\n3. three\n4. four\n\n",
+        );
+        let state = OutputState {
+            options: OutputOptions::default().with(PlainTextOptions {
+                link_urls: false,
+                code_blocks: false,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let result = to_plain_text(&ast, Some(state));
+        assert_eq!(result, "See docs\n\n3. three\n4. four");
+    }
+
+    #[test]
+    fn test_terminal_styles() {
+        let ast = parse_text("**bold** *em* ~~del~~ `code` [docs](https://example.com)");
+        let result = to_terminal(&ast, None);
+        assert!(result.contains("\x1b[1mbold\x1b[22m"));
+        assert!(result.contains("\x1b[3mem\x1b[23m"));
+        assert!(result.contains("\x1b[9mdel\x1b[29m"));
+        assert!(result.contains("\x1b[36mcode\x1b[39m"));
+        assert!(
+            result.contains("\x1b]8;;https://example.com\x1b\\\x1b[4mdocs\x1b[24m\x1b]8;;\x1b\\")
+        );
+
+        // Control characters in the document are dropped, so it can't write its own escapes
+        let ast = parse_text("a\x1b[31mred\x07 `b\x1b]0;x\x07` \u{9b}2J");
+        assert_eq!(
+            to_terminal(&ast, None),
+            "a[31mred \x1b[36mb]0;x\x1b[39m 2J\n"
+        );
+        let ast = parse_text("See[^\x1b[31m]");
+        assert_eq!(ast[1]["type"], "footnoteRef");
+        assert_eq!(to_terminal(&ast, None), "See[^[31m]\n");
+    }
+
+    #[test]
+    fn test_terminal_no_color() {
+        let ast = parse_text(
+            "A paragraph long enough to be wrapped at twenty columns\n\n> quoted\n\n* item\n\n```\ncode\n```\n\n",
+        );
+        let state = OutputState {
+            options: OutputOptions::default().with(TerminalOptions {
+                width: 20,
+                color: false,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let result = to_terminal(&ast, Some(state));
+        assert!(!result.contains('\x1b'));
+        assert_eq!(
+            result,
+            "A paragraph long\nenough to be wrapped\nat twenty columns\n\n│ quoted\n\n• item\n\n┌──────┐\n│ code │\n└──────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_latex() {
+        let ast = parse_text(
+            "## Costs & 50% _off_\n\nSee [docs](https://example.com/#top) and ![chart](chart.png)\n\n3. three\n4. four\n\n```rust\nlet x = 1;\n```\n\n",
+        );
+        let result = to_latex(&ast, None);
+        assert!(result.starts_with("\\subsection{Costs \\& 50\\% \\emph{off}}"));
+        assert!(result.contains("\\href{https://example.com/\\#top}{docs}"));
+        assert!(result.contains("\\includegraphics{chart.png}"));
+        assert!(result.contains("\\begin{enumerate}\n  \\setcounter{enumi}{2}\n  \\item three\n  \\item four\n\\end{enumerate}"));
+        // listings has no definition for Rust, so the language is left out
+        assert!(result.contains("\\begin{lstlisting}\nlet x = 1;\n\\end{lstlisting}"));
+
+        let ast = parse_text("```py\nprint(1)\n```\n\n```\n\\end{lstlisting}\n```\n\n");
+        let result = to_latex(&ast, None);
+        assert!(result.contains("\\begin{lstlisting}[language={Python}]\nprint(1)\n"));
+        assert!(result.contains("\\begin{lstlisting}\n\\end {lstlisting}\n\\end{lstlisting}"));
+
+        let ast = parse_text("1. one\n    * two\n        3. three\n\n");
+        let result = to_latex(&ast, None);
+        assert!(result.contains("\\setcounter{enumii}{2}"));
+    }
+
+    #[test]
+    fn test_escape_latex() {
+        assert_eq!(
+            escape_latex(r"a_b {c} ~ ^ \ $#"),
+            r"a\_b \{c\} \textasciitilde{} \textasciicircum{} \textbackslash{} \$\#"
+        );
+    }
+
+    #[test]
+    fn test_latex_standalone() {
+        let ast = parse_text("text");
+        let state = OutputState {
+            options: OutputOptions::default().with(LatexOptions {
+                standalone: true,
+                listings: false,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_latex(&ast, Some(state)),
+            "\\documentclass{article}\n\\usepackage{graphicx}\n\\usepackage{hyperref}\n\\usepackage[normalem]{ulem}\n\\begin{document}\ntext\n\\end{document}\n"
+        );
+    }
+
+    #[test]
+    fn test_roff() {
+        let ast = parse_text(
+            "## Options\n\nUse *--verbose* for **more** output\n\n* one\n* two\n\n```\n.hidden\n```\n\n",
+        );
+        let state = OutputState {
+            options: OutputOptions::default().with(RoffOptions {
+                title: "TOOL".to_string(),
+                section: "1".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_roff(&ast, Some(state)),
+            ".TH \"TOOL\" \"1\" \"\" \"\" \"\"\n.SS Options\n.PP\nUse \\fI\\-\\-verbose\\fR for \\fBmore\\fR output\n.IP \\(bu 2\none\n.IP \\(bu 2\ntwo\n.PP\n.RS 4\n.nf\n\\&.hidden\n.fi\n.RE\n"
+        );
+        let ast = parse_text("See ***a** b* `c`\n\n");
+        assert_eq!(
+            to_roff(&ast, None),
+            ".PP\nSee \\fI\\f(BIa\\fI b\\fR \\fBc\\fR\n"
+        );
+        let ast = parse_text("| a | b |\n| - | -: |\n| 1 | 2 |\n\n");
+        assert_eq!(
+            to_roff(&ast, None),
+            "'\\\" t\n.PP\n.TS\nlb rb\nl r.\na\tb\n1\t2\n.TE\n"
+        );
+    }
+
+    #[test]
+    fn test_slack() {
+        let ast = parse_text(
+            "# Release\n\n**New** *fast* ~~old~~ [docs](https://example.com?a=1&b=2) a < b\n\n* one\n    * nested\n* two\n\n",
+        );
+        assert_eq!(
+            to_slack(&ast, None),
+            "*Release*\n\n*New* _fast_ ~old~ <https://example.com?a=1&amp;b=2|docs> a &lt; b\n\n• one\n    • nested\n• two"
+        );
+        let ast = parse_text("* one\n    * two\n        1. three\n        2. four\n* five\n\n");
+        assert_eq!(
+            to_slack(&ast, None),
+            "• one\n    • two\n        1. three\n        2. four\n• five"
+        );
+    }
+
+    #[test]
+    fn test_slack_image() {
+        let ast = parse_text("![chart](https://example.com/chart.png)");
+        assert_eq!(
+            to_slack(&ast, None),
+            "<https://example.com/chart.png|chart>"
+        );
+    }
+
+    #[test]
+    fn test_telegram_markdown() {
+        let ast = parse_text(
+            "Version 1.2 is out! **New** *fast* ~~old~~ `a_b` [docs](https://example.com/(x))\n\n```rust\nlet s = \"`\";\n```\n\n",
+        );
+        assert_eq!(
+            to_telegram_markdown(&ast, None),
+            "Version 1\\.2 is out\\! *New* _fast_ ~old~ `a_b` [docs](https://example.com/(x\\))\n\n```rust\nlet s = \"\\`\";\n```"
+        );
+        let ast = parse_text("* one\n    * two\n        1. three\n        2. four\n* five\n\n");
+        assert_eq!(
+            to_telegram_markdown(&ast, None),
+            "• one\n    • two\n        1\\. three\n        2\\. four\n• five"
+        );
+        assert_eq!(
+            to_telegram_html(&ast, None),
+            "• one\n    • two\n        1. three\n        2. four\n• five"
+        );
+        let ast = parse_text("```c++\na\n```\n\n```a`b\nb\n```\n\n");
+        assert_eq!(
+            to_telegram_markdown(&ast, None),
+            "```c++\na\n```\n\n```\nb\n```"
+        );
+    }
+
+    #[test]
+    fn test_telegram_html() {
+        let ast = parse_text("**a < b** [docs](https://example.com)\n\n```rust\nx && y\n```\n\n");
+        assert_eq!(
+            to_telegram_html(&ast, None),
+            "<b>a &lt; b</b> <a href=\"https://example.com\">docs</a>\n\n<pre><code class=\"language-rust\">x &amp;&amp; y</code></pre>"
+        );
+    }
+
+    #[test]
+    fn test_matrix() {
+        let ast = parse_text(
+            "Hi **there** [docs](https://example.com) [bad](javascript:alert(1)) ![cat](mxc://example.org/cat)\n\n",
+        );
+        let message = to_matrix(&ast, None, None);
+        assert_eq!(
+            message.body,
+            "Hi there docs (https://example.com) bad (javascript:alert(1)) cat"
+        );
+        assert_eq!(
+            message.formatted_body,
+            "<p>Hi <strong>there</strong> <a href=\"https:&#x2F;&#x2F;example.com\">docs</a> bad <img src=\"mxc:&#x2F;&#x2F;example.org&#x2F;cat\" alt=\"cat\"></p>"
+        );
+        assert_eq!(message.to_json()["format"], "org.matrix.custom.html");
+
+        let message = to_matrix(&parse_text("[x](https://a \"t\")"), None, None);
+        assert_eq!(
+            message.formatted_body,
+            "<a href=\"https:&#x2F;&#x2F;a\">x</a>"
+        );
+    }
+
+    #[test]
+    fn test_matrix_reply() {
+        let reply = MatrixReply {
+            room_id: "!room:example.org".to_string(),
+            event_id: "$event".to_string(),
+            sender: "@alice:example.org".to_string(),
+            body: "> <@bob:example.org> older\n\nquestion?".to_string(),
+            formatted_body: Some("<mx-reply>older</mx-reply><em>question?</em>".to_string()),
+        };
+        let message = to_matrix(&parse_text("answer"), Some(&reply), None);
+        assert_eq!(message.body, "> <@alice:example.org> question?\n\nanswer");
+        assert!(message.formatted_body.starts_with("<mx-reply><blockquote>"));
+        assert!(message
+            .formatted_body
+            .ends_with("<br><em>question?</em></blockquote></mx-reply>answer"));
+        assert!(!message.formatted_body.contains("older"));
+    }
+
+    #[test]
+    fn test_jira() {
+        let ast = parse_text(
+            "## Incident\n\n**Impact** *minor* ~~none~~ `x` [runbook](https://example.com)\n\n> quoted\n\n1. first\n   * nested\n2. second\n\n```sh\nrestart\n```\n\n",
+        );
+        assert_eq!(
+            to_jira(&ast, None),
+            "h2. Incident\n\n*Impact* _minor_ -none- {{x}} [runbook|https://example.com]\n\n{quote}\nquoted\n{quote}\n\n# first\n#* nested\n# second\n\n{code:sh}\nrestart\n{code}"
+        );
+
+        let ast = parse_text("* one\n    1. two\n        * three\n\n[a|b](http://x.y/a|b]) ![a|b](http://x.y/c!.png)\n\n");
+        assert_eq!(
+            to_jira(&ast, None),
+            "* one\n*# two\n*#* three\n\n[a\\|b|http://x.y/a%7Cb%5D] !http://x.y/c%21.png|alt=a\\|b!"
+        );
+        let ast = parse_text("```md\nUse {code} blocks\n```\n\n");
+        assert_eq!(
+            to_jira(&ast, None),
+            "{noformat}\nUse {code} blocks\n{noformat}"
+        );
+    }
+
+    #[test]
+    fn test_nested_list() {
+        let result = markdown_to_html("* one\n    * nested\n        * deeper\n* two\n\n", None);
+        assert_eq!(
+            result,
+            "<ul><li>one\n<ul><li>nested\n<ul><li>deeper</li></ul></li></ul></li><li>two</li></ul>"
+        );
+        // Text in list items stops before ordered bullets, so they can start a nested list too
+        let result = markdown_to_html("1. one\n   1. nested\n   2. more\n2. two\n\n", None);
+        assert_eq!(
+            result,
+            "<ol start=\"1\"><li>one\n<ol start=\"1\"><li>nested</li><li>more</li></ol></li><li>two</li></ol>"
+        );
+        assert_eq!(
+            markdown_to_html("See\n2. not a list\n\n", None),
+            "<div class=\"paragraph\">See\n2. not a list</div>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_pandoc_json() {
+        let ast = parse_text(
+            "# Title\n\nSome ~~old~~ [text](https://example.com \"Home\")\n\n3. three\n4. four\n\n```rust\nfn main() {}\n```\n\n",
+        );
+        let document = to_pandoc_json(&ast, None);
+        assert_eq!(
+            document["pandoc-api-version"],
+            serde_json::json!([1, 23, 1])
+        );
+        assert_eq!(
+            document["blocks"],
+            serde_json::json!([
+                {"t": "Header", "c": [1, ["", [], []], [{"t": "Str", "c": "Title"}]]},
+                {"t": "Para", "c": [
+                    {"t": "Str", "c": "Some"},
+                    {"t": "Space"},
+                    {"t": "Strikeout", "c": [{"t": "Str", "c": "old"}]},
+                    {"t": "Space"},
+                    {"t": "Link", "c": [
+                        ["", [], []],
+                        [{"t": "Str", "c": "text"}],
+                        ["https://example.com", "Home"]
+                    ]}
+                ]},
+                {"t": "OrderedList", "c": [
+                    [3, {"t": "Decimal"}, {"t": "Period"}],
+                    [
+                        [{"t": "Plain", "c": [{"t": "Str", "c": "three"}]}],
+                        [{"t": "Plain", "c": [{"t": "Str", "c": "four"}]}]
+                    ]
+                ]},
+                {"t": "CodeBlock", "c": [["", ["rust"], []], "fn main() {}"]}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mdast_export() {
+        let parse = parser_for(create_default_rules());
+        assert!(!parse("# Title\n\n", None)[0].contains_key("position"));
+        let ast = parse(
+            "# Title\n\nSome *text*\n\n",
+            Some(State {
+                positions: true,
+                ..Default::default()
+            }),
+        );
+        let tree = to_mdast(&ast);
+        assert_eq!(tree["type"], "root");
+        assert_eq!(
+            tree["children"][0],
+            serde_json::json!({
+                "type": "heading",
+                "depth": 1,
+                "children": [{"type": "text", "value": "Title"}],
+                "position": {
+                    "start": {"line": 1, "column": 1, "offset": 0},
+                    "end": {"line": 1, "column": 8, "offset": 7}
+                }
+            })
+        );
+        let paragraph = &tree["children"][1];
+        assert_eq!(paragraph["type"], "paragraph");
+        assert_eq!(paragraph["children"][1]["type"], "emphasis");
+        assert_eq!(paragraph["position"]["start"]["line"], 3);
+    }
+
+    #[test]
+    fn test_mdast_import() {
+        let tree = serde_json::json!({
+            "type": "root",
+            "children": [
+                {"type": "paragraph", "children": [
+                    {"type": "text", "value": "a*b "},
+                    {"type": "simpleMarkdown:u", "children": [{"type": "text", "value": "under"}]},
+                    {"type": "text", "value": " "},
+                    {"type": "link", "url": "https://example.com", "title": null, "children": [
+                        {"type": "strong", "children": [{"type": "text", "value": "site"}]}
+                    ]}
+                ]},
+                {"type": "list", "ordered": true, "start": 2, "spread": false, "children": [
+                    {"type": "listItem", "spread": false, "children": [
+                        {"type": "paragraph", "children": [{"type": "text", "value": "two"}]}
+                    ]}
+                ]}
+            ]
+        });
+        let ast = from_mdast(&tree);
+        assert_eq!(
+            ast[0]["content"],
+            "a\\*b __under__ [**site**](https://example.com)"
+        );
+        assert_eq!(
+            markdown_to_html(&ast[0]["content"], None),
+            "a*b <strong>under</strong> <a href=\"https:&#x2F;&#x2F;example.com\"><strong>site</strong></a>"
+        );
+        assert_eq!(ast[3]["start"], "2");
+
+        // The default rules read `__` as strong, so underline comes back as strong
+        let round_trip = to_mdast(&ast);
+        let mut expected = tree["children"][0].clone();
+        expected["children"][1]["type"] = serde_json::json!("strong");
+        assert_eq!(round_trip["children"][0], expected);
+        assert_eq!(round_trip["children"][1]["start"], 2);
+    }
+
+    #[test]
+    fn test_commonmark_spec() {
+        let examples =
+            parse_spec_examples(include_str!("../tests/fixtures/commonmark_spec.json")).unwrap();
+        assert_eq!(examples.len(), 652);
+        let report = run_spec_examples(&examples, commonmark_to_html);
+        let failures: Vec<u64> = report
+            .failures
+            .iter()
+            .filter_map(|(example, _)| example.example)
+            .collect();
+        // Remove examples from this list as the preset gets closer to the spec
+        let known_failures: &[u64] = &[
+            1, 2, 3, 6, 7, 9, 13, 20, 21, 22, 23, 24, 25, 26, 27, 31, 32, 33, 34, 37, 38, 39, 40,
+            41, 43, 47, 48, 55, 56, 57, 58, 60, 61, 63, 65, 70, 74, 75, 76, 77, 78, 79, 81, 82, 85,
+            87, 88, 92, 94, 95, 96, 98, 99, 100, 101, 105, 111, 114, 115, 117, 124, 126, 127, 128,
+            130, 131, 132, 133, 135, 136, 137, 138, 139, 140, 141, 143, 144, 145, 146, 148, 149,
+            150, 151, 152, 153, 154, 155, 156, 157, 158, 159, 160, 161, 162, 163, 164, 165, 166,
+            167, 168, 169, 170, 171, 172, 173, 174, 175, 176, 177, 178, 179, 180, 181, 182, 183,
+            184, 185, 186, 187, 188, 189, 190, 191, 192, 193, 194, 195, 196, 198, 200, 201, 202,
+            203, 204, 205, 206, 207, 208, 210, 214, 215, 216, 217, 218, 225, 227, 234, 235, 236,
+            237, 239, 240, 241, 242, 245, 246, 249, 252, 255, 257, 262, 264, 266, 268, 273, 274,
+            276, 278, 280, 281, 282, 283, 284, 292, 293, 294, 295, 296, 297, 301, 302, 303, 305,
+            306, 307, 308, 309, 310, 311, 312, 313, 314, 315, 316, 317, 318, 319, 320, 321, 325,
+            326, 341, 342, 344, 346, 347, 352, 353, 354, 358, 359, 360, 362, 368, 369, 371, 373,
+            376, 379, 380, 383, 384, 385, 386, 387, 388, 389, 391, 392, 397, 398, 400, 401, 402,
+            407, 408, 409, 417, 418, 419, 421, 425, 426, 427, 430, 432, 435, 439, 443, 444, 445,
+            446, 447, 451, 455, 456, 457, 458, 459, 464, 466, 470, 471, 473, 474, 475, 476, 477,
+            478, 479, 480, 481, 489, 491, 492, 493, 494, 496, 497, 502, 503, 504, 505, 506, 507,
+            508, 513, 515, 518, 519, 520, 521, 524, 525, 526, 527, 528, 529, 530, 531, 532, 533,
+            534, 535, 536, 537, 538, 539, 540, 541, 542, 543, 544, 545, 546, 547, 549, 550, 552,
+            553, 554, 555, 556, 557, 558, 559, 560, 561, 562, 563, 564, 565, 566, 567, 568, 569,
+            570, 571, 573, 574, 575, 576, 577, 582, 583, 584, 585, 586, 587, 588, 589, 591, 592,
+            593, 598, 601, 603, 606, 613, 614, 615, 616, 617, 623, 625, 626, 627, 628, 629, 630,
+            631, 642, 643, 649,
+        ];
+        assert_eq!(failures, known_failures, "compliance changed:\n{}", report);
+    }
+
+    #[test]
+    fn test_builtin_highlighter() {
+        let spans = BuiltinHighlighter.highlight("rs", "let s = \"hi\"; // 'a\nx1 + 2");
+        let kinds: Vec<(&str, Option<TokenKind>)> = spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("let", Some(TokenKind::Keyword)),
+                (" s = ", None),
+                ("\"hi\"", Some(TokenKind::String)),
+                ("; ", None),
+                ("// 'a", Some(TokenKind::Comment)),
+                ("\nx1 + ", None),
+                ("2", Some(TokenKind::Number)),
+            ]
+        );
+        let plain = BuiltinHighlighter.highlight("brainfuck", "+[]");
+        assert_eq!(plain.len(), 1);
+        assert_eq!(plain[0].kind, None);
+    }
+
+    #[test]
+    fn test_highlighted_code_block() {
+        let state = OutputState {
+            highlighter: Some(std::rc::Rc::new(BuiltinHighlighter)),
+            ..Default::default()
+        };
+        let result = markdown_to_html("```py\nreturn None\n```\n\n", Some(state));
+        assert_eq!(
+            result,
+            "<pre><code class=\"markdown-code-py\"><span class=\"hl-keyword\">return</span> <span class=\"hl-literal\">None</span></code></pre>\n\n"
+        );
+
+        let state = OutputState {
+            highlighter: Some(std::rc::Rc::new(BuiltinHighlighter)),
+            ..Default::default()
+        };
+        let result = to_terminal(&parse_text("```py\nreturn\n```\n\n"), Some(state));
+        assert_eq!(
+            result,
+            "┌─ py ───┐\n│ \u{1b}[35mreturn\u{1b}[39m │\n└────────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let ast = parse_text(
+            "# Design\n\n## Goals\n\n### Speed\n\n#### Detail\n\nText\n\nNon-goals\n---------\n\n### Later\n\n",
+        );
+        let toc = table_of_contents(
+            &ast,
+            &TocOptions {
+                min_depth: 2,
+                max_depth: 3,
+            },
+        );
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].content, "Goals");
+        assert_eq!(toc[0].children[0].content, "Speed");
+        assert!(toc[0].children[0].children.is_empty());
+        assert_eq!(toc[1].content, "Non-goals");
+        assert_eq!(toc[1].children[0].content, "Later");
+
+        let list = toc_to_list(&toc, false);
+        assert_eq!(
+            output_for(create_default_rules(), "html")(&[list], None),
+            "<ul><li><a href=\"#goals\">Goals</a>\n<ul><li><a href=\"#speed\">Speed</a></li></ul></li><li><a href=\"#non-goals\">Non-goals</a>\n<ul><li><a href=\"#later\">Later</a></li></ul></li></ul>"
+        );
+        let list = toc_to_list(&toc, true);
+        assert!(output_for(create_default_rules(), "html")(&[list], None)
+            .starts_with("<ol><li><a href=\"#goals\">Goals</a>\n<ol start=\"1\">"));
+
+        // Links in headings aren't nested in the links to them
+        let ast = parse_text("## See [the *docs*](https://example.com)\n\n");
+        let list = toc_to_list(&table_of_contents(&ast, &TocOptions::default()), false);
+        assert_eq!(
+            output_for(create_default_rules(), "html")(&[list], None),
+            "<ul><li><a href=\"#see-the-docs\">See the <em>docs</em></a></li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_heading_slugs() {
+        assert_eq!(github_slug("What's new in v2.0?"), "whats-new-in-v20");
+        let mut slugger = Slugger::default();
+        assert_eq!(slugger.slug("Usage"), "usage");
+        assert_eq!(slugger.slug("Usage"), "usage-1");
+        assert_eq!(slugger.slug("Usage 1"), "usage-1-1");
+        assert_eq!(slugger.slug("Usage"), "usage-2");
+
+        let ast =
+            parse_text("# Setup *fast*\n\n## Setup fast\n\n## [Links](https://example.com)\n\n");
+        assert_eq!(
+            heading_slugs(&ast),
+            vec!["setup-fast", "setup-fast-1", "links"]
+        );
+        let result = markdown_to_html("## Setup\n\n## Setup\n\n", None);
+        assert_eq!(
+            result,
+            "<h2 id=\"setup\">Setup</h2>\n\n<h2 id=\"setup-1\">Setup</h2>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_vdom() {
+        let ast = parse_text("Hi **there** [x](javascript:alert(1))\n\n* one\n* `two`\n\n");
+        assert_eq!(
+            to_vdom(&ast, None),
+            serde_json::json!([
+                {"tag": "div", "props": {"className": "paragraph"}, "key": "0", "children": [
+                    "Hi ",
+                    {"tag": "strong", "props": {}, "key": "0.1", "children": ["there"]},
+                    " ",
+                    {"tag": "a", "props": {}, "key": "0.3", "children": ["x"]}
+                ]},
+                {"tag": "ul", "props": {}, "key": "1", "children": [
+                    {"tag": "li", "props": {}, "key": "1.0", "children": ["one"]},
+                    {"tag": "li", "props": {}, "key": "1.1", "children": [
+                        {"tag": "code", "props": {}, "key": "1.1.0", "children": ["two"]}
+                    ]}
+                ]}
+            ])
+        );
+        assert_eq!(
+            to_vdom(&ast, None),
+            to_vdom(
+                &parse_text("Hi **there** [x](javascript:alert(1))\n\n* one\n* `two`\n\n"),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_markdown_output() {
+        let source = "# Title *here*\n\nA **b** `c` [d](http://e.com \"t\") <http://f.com> 1\\*2\n\n* one\n* two\n    * three\n\n> quote\n\n```rs\nfn main() {}\n```\n\n";
+        let ast = parse_text(source);
+        let markdown = to_markdown(&ast, None);
+        assert_eq!(
+            markdown,
+            "# Title *here*\n\nA **b** `c` [d](http://e.com \"t\") <http://f.com> 1\\*2\n\n* one\n* two\n  * three\n\n> quote\n\n```rs\nfn main() {}\n```"
+        );
+        let html = output_for(create_default_rules(), "html");
+        assert_eq!(
+            html(&parse_text(&format!("{}\n\n", markdown)), None),
+            html(&ast, None)
+        );
+
+        // Text that reads as a mention stays text
+        let ast = vec![ASTNode::from([
+            ("type".to_string(), "text".to_string()),
+            (
+                "content".to_string(),
+                "ping @everyone or @heretic".to_string(),
+            ),
+        ])];
+        let markdown = to_markdown(&ast, None);
+        assert_eq!(markdown, "ping \\@everyone or @heretic");
+        assert_eq!(
+            to_plain_text(&parse_text(&format!("{}\n\n", markdown)), None),
+            "ping @everyone or @heretic"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let ast = parse_text("Hello **bold world** and [a link](http://example.com).\n\n");
+        assert_eq!(truncate(&ast, 100, "…", TruncateCount::Visible), ast);
+
+        let result = truncate(&ast, 16, "…", TruncateCount::Visible);
+        assert_eq!(to_markdown(&result, None), "Hello **bold worl…**");
+        let result = truncate(&ast, 25, "…", TruncateCount::Visible);
+        assert_eq!(
+            to_markdown(&result, None),
+            "Hello **bold world** and [a l…](http://example.com)"
+        );
+        let result = truncate(&ast, 16, "…", TruncateCount::Markdown);
+        assert_eq!(to_markdown(&result, None), "Hello **bold…**");
+
+        let ast = parse_text("Intro\n\n```\nlet a = 1;\nlet b = 2;\n```\n\n");
+        let result = truncate(&ast, 20, "...", TruncateCount::Visible);
+        assert_eq!(
+            to_markdown(&result, None),
+            "Intro\n\n```\nlet a = 1;...\n```"
+        );
+    }
+
+    #[test]
+    fn test_from_html() {
+        let html = "<meta charset=\"utf-8\"><h2>Hello <i>world</i></h2><p>Some <b>bold </b>and <a href=\"https://example.com/a b\" title=\"T\">a link</a> &amp; <code>x &lt; y</code><br>next<p>1. not a list<ol start=\"3\"><li>three<li>four<ul><li>nested</ul></ol><blockquote><p>quoted</p><p><s>gone</s> <u>under</u></p></blockquote><pre><code class=\"language-rust\">fn main() {}\n</code></pre><hr><img src=\"/a.png\" alt=\"pic\"><script>alert(1)</script>";
+        let ast = from_html(html);
+        assert_eq!(ast[0]["type"], "heading");
+        assert_eq!(ast[0]["level"], "2");
+        let list = ast.iter().find(|node| node["type"] == "list").unwrap();
+        assert_eq!(list["start"], "3");
+        assert_eq!(list_items(list), vec!["three", "four\n* nested"]);
+        assert_eq!(
+            html_to_markdown(html),
+            "## Hello *world*\n\nSome **bold** and [a link](https://example.com/a%20b \"T\") & `x < y`  \nnext\n\n1\\. not a list\n\n3. three\n4. four\n   * nested\n\n> quoted\n>\n> ~~gone~~ under\n\n```rust\nfn main() {}\n```\n\n---\n\n![pic](/a.png)"
+        );
+        // A `<` that can't start a tag is text
+        assert_eq!(html_to_markdown("a < b > c"), "a \\< b \\> c");
+        assert_eq!(html_to_markdown("<p>1 <2 <b>3</b></p>"), "1 \\<2 **3**");
+    }
+
+    #[test]
+    fn test_email_html() {
+        let ast = parse_text("See [setup](setup.html) and [home](/), ![logo](//cdn.example.com/logo.png)\n\n```\na < b\n```\n\n");
+        let state = OutputState {
+            options: OutputOptions::default().with(EmailOptions {
+                base_url: "https://example.com/docs/intro".to_string(),
+                theme: EmailTheme {
+                    link_color: "#ff0000".to_string(),
+                    ..Default::default()
+                },
+            }),
+            ..Default::default()
+        };
+        let result = to_email_html(&ast, Some(state));
+        assert!(!result.contains("class="));
+        assert!(result.contains("<a href=\"https:&#x2F;&#x2F;example.com&#x2F;docs&#x2F;setup.html\" style=\"color:#ff0000;text-decoration:underline;\">setup</a>"));
+        assert!(result.contains("<a href=\"https:&#x2F;&#x2F;example.com&#x2F;\""));
+        assert!(result.contains("src=\"https:&#x2F;&#x2F;cdn.example.com&#x2F;logo.png\""));
+        assert!(result.contains("<table role=\"presentation\""));
+        assert!(result.contains(
+            "white-space:pre-wrap;word-wrap:break-word;\">a &lt; b</pre></td></tr></table>"
+        ));
+
+        // Relative targets can't be resolved without a base url
+        let result = to_email_html(&ast, None);
+        assert!(result.contains("<a style=\"color:#0969da;text-decoration:underline;\">setup</a>"));
+    }
+
+    #[test]
+    fn test_table() {
+        let ast =
+            parse_text("| a | *b* | c |\n|:--|:-:|--:|\n| 1 | 2 |\n| 3 \\| 4 | 5 | 6 | 7 |\n\n");
+        assert_eq!(ast.len(), 1);
+        assert_eq!(node_value(&ast[0], "type"), "table");
+        assert_eq!(table_header(&ast[0]), vec!["a", "*b*", "c"]);
+        assert_eq!(table_align(&ast[0]), vec!["left", "center", "right"]);
+        assert_eq!(
+            table_cells(&ast[0]),
+            vec![vec!["1", "2", ""], vec!["3 \\| 4", "5", "6"]]
+        );
+        assert_eq!(
+            markdown_to_html("x | y\n--|--\n1 | 2\n\n", None),
+            "<table><thead><tr><th scope=\"col\">x</th><th scope=\"col\">y</th></tr></thead><tbody><tr><td>1</td><td>2</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            to_markdown(&ast, None),
+            "| a      | *b* |   c |\n| :----- | :-: | --: |\n| 1      |  2  |     |\n| 3 \\| 4 |  5  |   6 |"
+        );
+    }
+
+    #[test]
+    fn test_footnotes() {
+        let mut ast = parse_text(
+            "One[^b] two[^a] three[^B] four[^none].\n\n[^a]: First\n\n    Second[^c]\n\n[^b]: Bee\n[^c]: Sea\n[^unused]: No\n\n",
+        );
+        collect_footnotes(&mut ast);
+        let section = ast.last().unwrap();
+        assert_eq!(node_value(section, "type"), "footnotes");
+        let footnotes = footnote_items(section);
+        let labels: Vec<(&str, usize, usize)> = footnotes
+            .iter()
+            .map(|footnote| {
+                (
+                    footnote.label.as_str(),
+                    footnote.number,
+                    footnote.references,
+                )
+            })
+            .collect();
+        assert_eq!(labels, vec![("b", 1, 2), ("a", 2, 1), ("c", 3, 1)]);
+        assert_eq!(footnotes[1].content, "First\n\nSecond[^c]");
+        assert_eq!(
+            to_plain_text(&ast, None),
+            "One[1] two[2] three[1] four[^none].\n\n[1] Bee\n\n[2] First\n\nSecond[3]\n\n[3] Sea"
+        );
+
+        // References inside and outside lists are numbered in output order
+        let mut ast = parse_text(
+            "A[^a]\n* b[^b]\n\n* one[^c]\n    * two[^d]\n\nEnd[^e]\n\n[^a]: A\n[^b]: B\n[^c]: C\n[^d]: D\n[^e]: E\n\n",
+        );
+        collect_footnotes(&mut ast);
+        let labels: Vec<(String, usize)> = footnote_items(ast.last().unwrap())
+            .into_iter()
+            .map(|footnote| (footnote.label, footnote.number))
+            .collect();
+        assert_eq!(
+            labels,
+            [("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5)]
+                .map(|(label, number)| (label.to_string(), number))
+        );
+        assert!(
+            to_plain_text(&ast, None).starts_with("A[1]\n* b[2]\n\n- one[3]\n- two[4]\n\nEnd[5]")
+        );
+
+        let html = markdown_to_html("A[^1] b[^1]\n\n[^1]: Note\n\n", None);
+        assert_eq!(
+            html,
+            "<div class=\"paragraph\">A<sup><a href=\"#fn-1\" id=\"fnref-1\" class=\"footnote-ref\">1</a></sup> b<sup><a href=\"#fn-1\" id=\"fnref-1-2\" class=\"footnote-ref\">1</a></sup></div>\n\n<section class=\"footnotes\"><ol><li id=\"fn-1\"><div class=\"paragraph\">Note <a href=\"#fnref-1\" class=\"footnote-backref\" aria-label=\"Back to reference\">↩</a> <a href=\"#fnref-1-2\" class=\"footnote-backref\" aria-label=\"Back to reference\">↩<sup>2</sup></a></div></li></ol></section>"
+        );
+
+        // References in link text aren't links, and get no back-reference
+        let source = "See [link [^1]](http://x) and[^1]\n\n[^1]: Note\n\n";
+        assert_eq!(
+            markdown_to_html(source, None),
+            "<div class=\"paragraph\">See <a href=\"http:&#x2F;&#x2F;x\">link <sup>1</sup></a> and<sup><a href=\"#fn-1\" id=\"fnref-1\" class=\"footnote-ref\">1</a></sup></div>\n\n<section class=\"footnotes\"><ol><li id=\"fn-1\"><div class=\"paragraph\">Note <a href=\"#fnref-1\" class=\"footnote-backref\" aria-label=\"Back to reference\">↩</a></div></li></ol></section>"
+        );
+        let mut ast = parse_text(source);
+        collect_footnotes(&mut ast);
+        let vdom = to_vdom(&ast, None);
+        assert_eq!(
+            vdom[0]["children"][1]["children"][1],
+            serde_json::json!({"tag": "sup", "props": {}, "key": "0.1.1", "children": ["1"]})
+        );
+
+        // Urls in link content are not searched for references as new links
+        assert_eq!(
+            markdown_to_html("See <https://example.com>\n\n", None),
+            "<div class=\"paragraph\">See <a href=\"https:&#x2F;&#x2F;example.com\">https:&#x2F;&#x2F;example.com</a></div>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_task_list() {
+        let source =
+            "Todo:\n\n- [ ] one\n- [x] two\n    - [ ] nested\n- three\n\n> 1. [X] quoted\n\n";
+        let ast = parse_text(source);
+        assert_eq!(
+            list_items(&ast[3]),
+            vec!["one", "two\n- [ ] nested", "three"]
+        );
+        assert_eq!(list_checked(&ast[3]), vec![Some(false), Some(true), None]);
+        assert_eq!(
+            markdown_to_html("* [x] done\n* todo\n\n", None),
+            "<ul class=\"contains-task-list\"><li class=\"task-list-item\"><input type=\"checkbox\" checked=\"checked\" disabled=\"disabled\"> done</li><li>todo</li></ul>"
+        );
+
+        assert_eq!(
+            toggle_task_item(source, 0).unwrap(),
+            source.replacen("- [ ] one", "- [x] one", 1)
+        );
+        assert_eq!(
+            toggle_task_item(source, 2).unwrap(),
+            source.replacen("- [ ] nested", "- [x] nested", 1)
+        );
+        assert_eq!(
+            toggle_task_item(source, 3).unwrap(),
+            source.replacen("[X] quoted", "[ ] quoted", 1)
+        );
+        assert_eq!(toggle_task_item(source, 4), None);
+
+        // Lines in code blocks of list items aren't tasks
+        let source = "- item\n\n  ```\n  - [ ] in code\n  ```\n- [ ] real\n";
+        assert_eq!(
+            toggle_task_item(source, 0).unwrap(),
+            source.replacen("- [ ] real", "- [x] real", 1)
+        );
+        assert_eq!(toggle_task_item(source, 1), None);
+        // Offsets are mapped back through tabs and CRLF line endings
+        let source = "*\t[ ] tab\r\n\r\n> * [x] quoted\r\n>   * [ ] nested\r\n\r\n";
+        assert_eq!(
+            toggle_task_item(source, 2).unwrap(),
+            source.replacen("[ ] nested", "[x] nested", 1)
+        );
+    }
+
+    #[test]
+    fn test_spoiler() {
+        let ast = parser_for(create_default_rules())(
+            "a ||b `x|| y` *c*|| ~~||d||~~",
+            Some(State {
+                inline: true,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(ast[1]["type"], "spoiler");
+        assert_eq!(ast[1]["content"], "b `x|| y` *c*");
+        assert_eq!(ast[3]["type"], "del");
+        assert_eq!(
+            markdown_to_html("||b *c*||", None),
+            "<span class=\"spoiler\">b <em>c</em></span>"
+        );
+        assert_eq!(to_plain_text(&ast, None), "a ████ ████");
+        assert_eq!(
+            to_telegram_html(&ast, None),
+            "a <tg-spoiler>b <code>x|| y</code> <i>c</i></tg-spoiler> <s><tg-spoiler>d</tg-spoiler></s>"
+        );
+        assert_eq!(to_markdown(&ast, None), "a ||b `x|| y` *c*|| ~~||d||~~");
+    }
+
+    #[test]
+    fn test_mention() {
+        struct Names;
+        impl MentionResolver for Names {
+            fn resolve(&self, kind: MentionKind, id: &str) -> Option<String> {
+                match (kind, id) {
+                    (MentionKind::User, "80351110224678912") => Some("Nelly".to_string()),
+                    (MentionKind::Channel, "1") => Some("general".to_string()),
+                    _ => None,
+                }
+            }
+        }
+
+        let ast = parser_for(create_default_rules())(
+            "<@!80351110224678912> <@&2> <#1> @here a@everyone.com <a@b.c>",
+            Some(State {
+                inline: true,
+                ..Default::default()
+            }),
+        );
+        let mentions: Vec<_> = ast
+            .iter()
+            .filter(|node| node["type"] == "mention")
+            .map(|node| (mention_kind(node), node.get("id").cloned()))
+            .collect();
+        assert_eq!(
+            mentions,
+            vec![
+                (
+                    Some(MentionKind::User),
+                    Some("80351110224678912".to_string())
+                ),
+                (Some(MentionKind::Role), Some("2".to_string())),
+                (Some(MentionKind::Channel), Some("1".to_string())),
+                (Some(MentionKind::Here), None),
+            ]
+        );
+        assert_eq!(ast.last().unwrap()["target"], "mailto:a@b.c");
+
+        let state = OutputState {
+            mentions: Some(std::rc::Rc::new(Names)),
+            ..Default::default()
+        };
+        assert_eq!(
+            markdown_to_html("<@80351110224678912> in <#1>", Some(state.clone())),
+            "<span class=\"mention mention-user\" data-id=\"80351110224678912\">@Nelly</span> in <span class=\"mention mention-channel\" data-id=\"1\">#general</span>"
+        );
+        assert_eq!(
+            to_plain_text(&ast, None),
+            "@80351110224678912 @2 #1 @here a@everyone.com a@b.c"
+        );
+        assert_eq!(
+            to_plain_text(&ast, Some(state.clone())),
+            "@Nelly @2 #general @here a@everyone.com a@b.c"
+        );
+        let channel = &ast[4..5];
+        assert_eq!(to_slack(channel, Some(state.clone())), "#general");
+        assert_eq!(
+            to_pandoc_json(channel, Some(state.clone()))["blocks"][0]["c"][0]["c"][1],
+            serde_json::json!([{"t": "Str", "c": "#general"}])
+        );
+        assert_eq!(
+            to_vdom(channel, Some(state.clone()))[0]["children"],
+            serde_json::json!(["#general"])
+        );
+        assert_eq!(
+            to_markdown(&ast, None),
+            "<@80351110224678912> <@&2> <#1> @here a\\@everyone.com <a@b.c>"
+        );
+    }
+
+    #[test]
+    fn test_emoji() {
+        let ast = parser_for(create_default_rules())(
+            "<:blob:123> <a:dance_party:456>",
+            Some(State {
+                inline: true,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(ast[0]["type"], "emoji");
+        assert_eq!(
+            (ast[2]["name"].as_str(), ast[2]["id"].as_str()),
+            ("dance_party", "456")
+        );
+        assert_eq!(ast[0]["animated"], "false");
+        assert_eq!(ast[2]["animated"], "true");
+        assert_eq!(
+            emoji_url(&ast[2], &EmojiOptions::default()),
+            "https://cdn.discordapp.com/emojis/456.gif?size=48"
+        );
+
+        let state = OutputState {
+            options: OutputOptions::default().with(EmojiOptions {
+                base_url: "https://emoji.example.com".to_string(),
+                size: 32,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            markdown_to_html("<:blob:123>", Some(state.clone())),
+            "<img class=\"emoji\" src=\"https:&#x2F;&#x2F;emoji.example.com&#x2F;123.png?size=32\" alt=\":blob:\" title=\":blob:\">"
+        );
+        assert!(to_email_html(&ast, Some(state))
+            .contains("src=\"https:&#x2F;&#x2F;emoji.example.com&#x2F;123.png?size=32\""));
+        assert_eq!(to_plain_text(&ast, None), ":blob: :dance_party:");
+        assert_eq!(to_markdown(&ast, None), "<:blob:123> <a:dance_party:456>");
+    }
+
+    #[test]
+    fn test_block_capture_lengths() {
+        // Each block consumes its whole match, so the blocks after it still parse
+        let result = parse_text("# Title\n\n---\n\n```js\ncode\n```\n\nafter\n\n");
+        let types: Vec<&str> = result.iter().map(|node| node["type"].as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "heading",
+                "newline",
+                "newline",
+                "hr",
+                "codeBlock",
+                "newline",
+                "newline",
+                "paragraph",
+                "newline",
+                "newline"
+            ]
+        );
+        assert_eq!(result[4]["content"], "code");
+        assert_eq!(result[7]["content"], "after");
+    }
+
+    #[test]
+    fn test_inline_capture_lengths() {
+        let result = parse_text("\\*a  \nb <https://x.y> <u@x.y> ~~d~~ e");
+        let nodes: Vec<(&str, &str)> = result
+            .iter()
+            .map(|node| (node_value(node, "type"), node_value(node, "content")))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                ("text", "*"),
+                ("text", "a"),
+                ("br", ""),
+                ("text", "b "),
+                ("link", "https://x.y"),
+                ("text", " "),
+                ("link", "u@x.y"),
+                ("text", " "),
+                ("del", "d"),
+                ("text", " e")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_title() {
+        let result = parse_text("[a](<b> \"t\") [c](d(e) 'f')");
+        assert_eq!(result[0]["target"], "b");
+        assert_eq!(result[0]["title"], "t");
+        assert_eq!(result[2]["target"], "d(e)");
+        assert_eq!(result[2]["title"], "f");
+    }
+
+    #[test]
+    fn test_list_items() {
+        // Items keep their bullets; `list_items` strips them and the task markers
+        let result = parse_text("* one\n  more\n* [x] two\n    * nested\n\n");
+        let items: Vec<String> = serde_json::from_str(&result[0]["items"]).unwrap();
+        assert_eq!(items, ["* one\n  more\n", "* [x] two\n    * nested\n"]);
+        assert_eq!(list_items(&result[0]), ["one\nmore", "two\n* nested"]);
+        assert_eq!(list_checked(&result[0]), [None, Some(true)]);
+    }
+}
@@ -0,0 +1,315 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_source;
+use crate::footnotes::{footnote_items, output_footnote, reference_source};
+use crate::mention::mention_source;
+use crate::output::{
+    align_cell, column_widths, list_checked, list_items, node_value, output_block, output_for,
+    output_inline, output_link_content, output_list_item, output_table_rows, table_align,
+    task_marker, OutputFunction, OutputState,
+};
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Escapes the characters the default rules treat as markup in inline content,
+/// and the `@` of text that would be read as an `@everyone` or `@here` mention.
+pub fn escape_markdown(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut prev = None;
+    for (i, c) in text.char_indices() {
+        // Like the mention rule, `@` in the middle of a word isn't a mention. The
+        // text before `text` isn't known, so an `@` starting it is escaped
+        let mention =
+            c == '@' && !prev.is_some_and(char::is_alphanumeric) && starts_mention(&text[i + 1..]);
+        prev = Some(c);
+        if "\\`*_~[]<>|!".contains(c) || mention {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+fn starts_mention(text: &str) -> bool {
+    ["everyone", "here"].iter().any(|name| {
+        text.strip_prefix(name)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    })
+}
+
+/// Escapes what would start a heading or list at the start of a paragraph.
+pub(crate) fn escape_block_start(paragraph: &str) -> String {
+    let digits = paragraph.len()
+        - paragraph
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    if paragraph.starts_with(['#', '-', '+']) {
+        format!("\\{}", paragraph)
+    } else if digits > 0 && paragraph[digits..].starts_with(". ") {
+        format!("{}\\{}", &paragraph[..digits], &paragraph[digits..])
+    } else {
+        paragraph.to_string()
+    }
+}
+
+/// Wraps `value` in enough backticks that none inside it close the span.
+pub(crate) fn code_span(value: &str) -> String {
+    let longest_run = value.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run + 1);
+    if value.starts_with('`') || value.ends_with('`') {
+        format!("{} {} {}", fence, value, fence)
+    } else {
+        format!("{}{}{}", fence, value, fence)
+    }
+}
+
+/// Formats a link or image destination, with its title if there is one. The
+/// link rules don't allow spaces in targets, so they are percent-encoded.
+pub(crate) fn link_destination(target: &str, title: &str) -> String {
+    let target = target.replace(' ', "%20");
+    if title.is_empty() {
+        target
+    } else {
+        format!("{} \"{}\"", target, title.replace('"', "\\\""))
+    }
+}
+
+/// Indents every line after the first, leaving blank lines empty.
+fn indent_continuation(text: &str, indent: &str) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prints a footnote definition. Following lines are indented by four spaces,
+/// as the `footnoteDef` rule expects.
+fn footnote_definition(label: &str, content: &str) -> String {
+    format!(
+        "[^{}]: {}\n\n",
+        label,
+        indent_continuation(content.trim(), "    ")
+    )
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let level: usize = node_value(node, "level").parse().unwrap_or(1);
+            format!(
+                "{} {}\n\n",
+                "#".repeat(level),
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("hr", |_node, _nested, _state| "---\n\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            let content = node_value(node, "content");
+            let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+            let fence = "`".repeat(longest_run.max(2) + 1);
+            format!(
+                "{}{}\n{}\n{}\n\n",
+                fence,
+                node_value(node, "lang"),
+                content,
+                fence
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            let quoted: Vec<String> = EXTRA_NEWLINES_R
+                .replace_all(content.trim(), "\n\n")
+                .split('\n')
+                .map(|line| {
+                    if line.is_empty() {
+                        ">".to_string()
+                    } else {
+                        format!("> {}", line)
+                    }
+                })
+                .collect();
+            format!("{}\n\n", quoted.join("\n"))
+        }),
+        ("list", |node, nested, state| {
+            let ordered = node_value(node, "ordered") == "true";
+            let start: usize = node_value(node, "start").parse().unwrap_or(1);
+            let checked = list_checked(node);
+            let items: Vec<String> = list_items(node)
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let bullet = if ordered {
+                        format!("{}.", start + i)
+                    } else {
+                        "*".to_string()
+                    };
+                    let content = output_list_item(node, nested, item, state);
+                    let indent = " ".repeat(bullet.len() + 1);
+                    format!(
+                        "{} {}{}",
+                        bullet,
+                        task_marker(checked[i], "[ ] ", "[x] "),
+                        indent_continuation(content.trim(), &indent)
+                    )
+                })
+                .collect();
+            // Nested lists follow the text of their item without a blank line
+            if !state.lists.is_empty() {
+                format!("{}\n", items.join("\n"))
+            } else {
+                format!("{}\n\n", items.join("\n"))
+            }
+        }),
+        ("table", |node, nested, state| {
+            let mut rows = output_table_rows(node, nested, state);
+            let align = table_align(node);
+            // Alignment markers need at least three columns
+            let widths: Vec<usize> = column_widths(&rows, |cell| cell.chars().count())
+                .iter()
+                .map(|width| (*width).max(3))
+                .collect();
+            let markers: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(
+                    |(column, width)| match align.get(column).map_or("", String::as_str) {
+                        "left" => format!(":{}", "-".repeat(width - 1)),
+                        "center" => format!(":{}:", "-".repeat(width - 2)),
+                        "right" => format!("{}:", "-".repeat(width - 1)),
+                        _ => "-".repeat(*width),
+                    },
+                )
+                .collect();
+            rows.insert(1, markers);
+            let lines: Vec<String> = rows
+                .iter()
+                .map(|row| {
+                    let cells: Vec<String> = row
+                        .iter()
+                        .enumerate()
+                        .map(|(column, cell)| {
+                            let align = align.get(column).map_or("", String::as_str);
+                            align_cell(cell, cell.chars().count(), widths[column], align)
+                        })
+                        .collect();
+                    format!("| {} |", cells.join(" | "))
+                })
+                .collect();
+            format!("{}\n\n", lines.join("\n"))
+        }),
+        ("def", |node, _nested, _state| {
+            format!(
+                "[{}]: {}\n\n",
+                node_value(node, "def"),
+                link_destination(node_value(node, "target"), node_value(node, "title"))
+            )
+        }),
+        ("footnoteDef", |node, nested, state| {
+            let content = output_block(
+                nested,
+                &format!("{}\n\n", node_value(node, "content")),
+                state,
+            );
+            footnote_definition(node_value(node, "label"), &content)
+        }),
+        ("footnotes", |node, nested, state| {
+            let mut result = "\n\n".to_string();
+            for footnote in footnote_items(node) {
+                let content = output_footnote(nested, &footnote, state);
+                result.push_str(&footnote_definition(&footnote.label, &content));
+            }
+            result
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            format!("{}\n\n", escape_block_start(&content))
+        }),
+        ("link", |node, nested, state| {
+            let target = node_value(node, "target");
+            let content = node_value(node, "content");
+            let is_autolink = node_value(node, "title").is_empty()
+                && (target == content || target.strip_prefix("mailto:") == Some(content))
+                && !content.contains([' ', '<', '>']);
+            if is_autolink && !state.in_link {
+                return format!("<{}>", content);
+            }
+            format!(
+                "[{}]({})",
+                output_link_content(node, nested, state),
+                link_destination(target, node_value(node, "title"))
+            )
+        }),
+        ("image", |node, _nested, _state| {
+            format!(
+                "![{}]({})",
+                escape_markdown(node_value(node, "alt")),
+                link_destination(node_value(node, "target"), node_value(node, "title"))
+            )
+        }),
+        ("em", |node, nested, state| {
+            format!(
+                "*{}*",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("strong", |node, nested, state| {
+            format!(
+                "**{}**",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("u", |node, nested, state| {
+            format!(
+                "__{}__",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("del", |node, nested, state| {
+            format!(
+                "~~{}~~",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("spoiler", |node, nested, state| {
+            format!(
+                "||{}||",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            code_span(node_value(node, "content"))
+        }),
+        ("footnoteRef", |node, _nested, _state| {
+            reference_source(node)
+        }),
+        ("br", |_node, _nested, _state| "  \n".to_string()),
+        ("mention", |node, _nested, _state| mention_source(node)),
+        ("emoji", |node, _nested, _state| emoji_source(node)),
+        ("text", |node, _nested, _state| {
+            escape_markdown(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Outputs `ast` as markdown that the default rules parse back to the same
+/// nodes, e.g. to store an AST that was edited or built by hand. The one
+/// exception is `u`: it is written as `__`, which the default rules read as
+/// `strong`.
+pub fn to_markdown(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "markdown")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
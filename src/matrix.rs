@@ -0,0 +1,334 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_items, next_reference, output_footnote, reference_source};
+use crate::html::{html_tag, sanitize_text};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, task_marker, OutputFunction,
+    OutputState,
+};
+use crate::plain_text::to_plain_text;
+
+lazy_static::lazy_static! {
+    static ref ALLOWED_HREF_R: Regex = Regex::new(r"(?i)^(?:https?|ftp|mailto|magnet):").unwrap();
+    static ref MXC_URL_R: Regex = Regex::new(r"^mxc://").unwrap();
+}
+
+/// The event being replied to, used to build the reply fallback of a message.
+#[derive(Debug, Clone, Default)]
+pub struct MatrixReply {
+    pub room_id: String,
+    pub event_id: String,
+    pub sender: String,
+    /// Plain text `body` of the event being replied to.
+    pub body: String,
+    /// `formatted_body` of the event being replied to, if it has one.
+    pub formatted_body: Option<String>,
+}
+
+/// The `body` and `formatted_body` pair of an `m.room.message` event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixMessage {
+    pub body: String,
+    pub formatted_body: String,
+}
+
+impl MatrixMessage {
+    /// Returns the content of an `m.text` message event.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "msgtype": "m.text",
+            "body": self.body,
+            "format": "org.matrix.custom.html",
+            "formatted_body": self.formatted_body,
+        })
+    }
+}
+
+/// Returns `url` if the Matrix spec allows it as a link `href`.
+fn allowed_href(url: &str) -> Option<&str> {
+    if ALLOWED_HREF_R.is_match(url).unwrap_or(false) {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Output functions for the `matrix` format. Only the tags and attributes the
+/// Matrix spec allows in `org.matrix.custom.html` are emitted.
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            html_tag(
+                &format!("h{}", node_value(node, "level")),
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("hr", |_node, _nested, _state| "<hr>".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            let class_name = if node_value(node, "lang").is_empty() {
+                String::new()
+            } else {
+                format!("language-{}", node_value(node, "lang"))
+            };
+            let code_block = html_tag(
+                "code",
+                &sanitize_text(node_value(node, "content")),
+                &[("class", &class_name)],
+                true,
+            );
+            html_tag("pre", &code_block, &[], true)
+        }),
+        ("blockQuote", |node, nested, state| {
+            html_tag(
+                "blockquote",
+                &output_block(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("list", |node, nested, state| {
+            // Matrix doesn't allow `input`, so tasks get a ballot box character
+            let items: String = list_items(node)
+                .iter()
+                .zip(list_checked(node))
+                .map(|(item, checked)| {
+                    let content = output_list_item(node, nested, item, state);
+                    let task = task_marker(checked, "☐ ", "☑ ");
+                    html_tag("li", &format!("{}{}", task, content), &[], true)
+                })
+                .collect();
+            if node_value(node, "ordered") == "true" {
+                html_tag("ol", &items, &[("start", node_value(node, "start"))], true)
+            } else {
+                html_tag("ul", &items, &[], true)
+            }
+        }),
+        // The spec allows tables but no attributes on them, so columns aren't aligned
+        ("table", |node, nested, state| {
+            let rows = output_table_rows(node, nested, state);
+            let row_html = |row: &Vec<String>, tag: &str| {
+                let cells: String = row
+                    .iter()
+                    .map(|cell| html_tag(tag, cell, &[], true))
+                    .collect();
+                html_tag("tr", &cells, &[], true)
+            };
+            let body: String = rows.iter().skip(1).map(|row| row_html(row, "td")).collect();
+            let head = rows
+                .first()
+                .map_or(String::new(), |row| row_html(row, "th"));
+            html_tag(
+                "table",
+                &format!(
+                    "{}{}",
+                    html_tag("thead", &head, &[], true),
+                    html_tag("tbody", &body, &[], true)
+                ),
+                &[],
+                true,
+            )
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        // Matrix strips `id` attributes, so footnotes can't link to each other
+        ("footnotes", |node, nested, state| {
+            let items: String = footnote_items(node)
+                .iter()
+                .map(|footnote| {
+                    html_tag("li", &output_footnote(nested, footnote, state), &[], true)
+                })
+                .collect();
+            format!("<hr>{}", html_tag("ol", &items, &[], true))
+        }),
+        ("newline", |_node, _nested, _state| String::new()),
+        ("paragraph", |node, nested, state| {
+            html_tag(
+                "p",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        // `a` only allows `href` and `target` in Matrix, so titles are dropped
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            match allowed_href(node_value(node, "target")) {
+                Some(href) => html_tag("a", &content, &[("href", href)], true),
+                None => content,
+            }
+        }),
+        // Matrix only allows images from the content repository, so others become links
+        ("image", |node, _nested, _state| {
+            let target = node_value(node, "target");
+            if MXC_URL_R.is_match(target).unwrap_or(false) {
+                html_tag(
+                    "img",
+                    "",
+                    &[
+                        ("src", target),
+                        ("alt", node_value(node, "alt")),
+                        ("title", node_value(node, "title")),
+                    ],
+                    false,
+                )
+            } else {
+                let alt = sanitize_text(node_value(node, "alt"));
+                match allowed_href(target) {
+                    Some(href) => html_tag("a", &alt, &[("href", href)], true),
+                    None => alt,
+                }
+            }
+        }),
+        ("em", |node, nested, state| {
+            html_tag(
+                "em",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("strong", |node, nested, state| {
+            html_tag(
+                "strong",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("u", |node, nested, state| {
+            html_tag(
+                "u",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        ("del", |node, nested, state| {
+            html_tag(
+                "del",
+                &output_inline(nested, node_value(node, "content"), state),
+                &[],
+                true,
+            )
+        }),
+        // `html_tag` leaves out empty attributes, so the spoiler has no reason
+        ("spoiler", |node, nested, state| {
+            format!(
+                "<span data-mx-spoiler>{}</span>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            html_tag(
+                "code",
+                &sanitize_text(node_value(node, "content")),
+                &[],
+                true,
+            )
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => html_tag("sup", &footnote.number.to_string(), &[], true),
+                None => sanitize_text(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "<br>".to_string()),
+        ("mention", |node, _nested, state| {
+            sanitize_text(&mention_text(node, state.mentions.as_deref()))
+        }),
+        // Matrix only shows images from its own content repository
+        ("emoji", |node, _nested, _state| {
+            sanitize_text(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            sanitize_text(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Strips the `<mx-reply>` fallback from a `formatted_body`, as required before quoting it.
+pub fn strip_reply_fallback(formatted_body: &str) -> String {
+    match (
+        formatted_body.find("<mx-reply>"),
+        formatted_body.find("</mx-reply>"),
+    ) {
+        (Some(start), Some(end)) if start < end => format!(
+            "{}{}",
+            &formatted_body[..start],
+            &formatted_body[end + "</mx-reply>".len()..]
+        ),
+        _ => formatted_body.to_string(),
+    }
+}
+
+/// Strips the `> ` quoted reply fallback lines from the start of a `body`.
+pub fn strip_body_reply_fallback(body: &str) -> String {
+    if !body.starts_with("> ") {
+        return body.to_string();
+    }
+    body.lines()
+        .skip_while(|line| line.starts_with('>'))
+        .skip_while(|line| line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Converts `ast` to the `body` and `formatted_body` of a Matrix message,
+/// prepending the reply fallback of `reply` if given. The `body` is output
+/// like `to_plain_text` with the same `state`.
+pub fn to_matrix(
+    ast: &[ASTNode],
+    reply: Option<&MatrixReply>,
+    state: Option<OutputState>,
+) -> MatrixMessage {
+    let mut body = to_plain_text(ast, state.clone());
+    let mut formatted_body = output_for(create_default_rules(), "matrix")(ast, state);
+
+    if let Some(reply) = reply {
+        let quoted_body = strip_body_reply_fallback(&reply.body);
+        let mut lines = quoted_body.lines();
+        let mut fallback = format!("> <{}> {}\n", reply.sender, lines.next().unwrap_or(""));
+        for line in lines {
+            fallback.push_str(&format!("> {}\n", line));
+        }
+        body = format!("{}\n{}", fallback, body);
+
+        let quoted_formatted_body = match &reply.formatted_body {
+            Some(formatted_body) => strip_reply_fallback(formatted_body),
+            None => sanitize_text(&quoted_body).replace('\n', "<br>"),
+        };
+        let event_link = format!("https://matrix.to/#/{}/{}", reply.room_id, reply.event_id);
+        let sender_link = format!("https://matrix.to/#/{}", reply.sender);
+        formatted_body = format!(
+            "<mx-reply>{}</mx-reply>{}",
+            html_tag(
+                "blockquote",
+                &format!(
+                    "{} {}<br>{}",
+                    html_tag("a", "In reply to", &[("href", &event_link)], true),
+                    html_tag(
+                        "a",
+                        &sanitize_text(&reply.sender),
+                        &[("href", &sender_link)],
+                        true
+                    ),
+                    quoted_formatted_body
+                ),
+                &[],
+                true,
+            ),
+            formatted_body
+        );
+    }
+
+    MatrixMessage {
+        body,
+        formatted_body,
+    }
+}
@@ -0,0 +1,626 @@
+use serde_json::{json, Map, Value};
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::footnotes::footnote_items;
+use crate::markdown::{code_span, escape_markdown, link_destination, to_markdown};
+use crate::output::{
+    list_checked, list_items, node_value, set_list_items, table_align, table_cells, table_header,
+};
+use crate::parse::NestedParser;
+
+/// Prefix of the mdast node types used for nodes mdast has no equivalent for,
+/// e.g. `simpleMarkdown:u`.
+pub const MDAST_CUSTOM_PREFIX: &str = "simpleMarkdown:";
+
+const BLOCK_TYPES: [&str; 11] = [
+    "heading",
+    "hr",
+    "codeBlock",
+    "blockQuote",
+    "list",
+    "table",
+    "paragraph",
+    "def",
+    "footnoteDef",
+    "footnotes",
+    "newline",
+];
+
+fn node_position(node: &ASTNode) -> Option<Value> {
+    node.get("position")
+        .and_then(|position| serde_json::from_str(position).ok())
+}
+
+fn set_position(node: &mut Value, position: Option<Value>) {
+    if let Some(position) = position {
+        node["position"] = position;
+    }
+}
+
+fn optional(value: &str) -> Value {
+    if value.is_empty() {
+        Value::Null
+    } else {
+        json!(value)
+    }
+}
+
+struct MdastWriter {
+    nested: NestedParser,
+}
+
+impl MdastWriter {
+    /// Converts `ast` to mdast block content, wrapping runs of inline nodes in
+    /// paragraphs. Only the nodes of the document itself carry spans, so
+    /// positions are kept at the top level.
+    fn blocks(&self, ast: &[ASTNode], top_level: bool) -> Vec<Value> {
+        let mut blocks = Vec::new();
+        let mut inlines = Vec::new();
+        let mut run_position: Option<Value> = None;
+        for node in ast {
+            let position = if top_level { node_position(node) } else { None };
+            if node_value(node, "type") == "footnotes" {
+                flush_paragraph(&mut blocks, &mut inlines, run_position.take());
+                for footnote in footnote_items(node) {
+                    blocks.push(self.footnote_definition(&footnote.label, &footnote.content));
+                }
+            } else if BLOCK_TYPES.contains(&node_value(node, "type")) {
+                flush_paragraph(&mut blocks, &mut inlines, run_position.take());
+                if let Some(mut block) = self.block(node) {
+                    set_position(&mut block, position);
+                    blocks.push(block);
+                }
+            } else if let Some(mut inline) = self.inline(node) {
+                if let Some(position) = &position {
+                    run_position = Some(match run_position.take() {
+                        Some(run) => json!({ "start": run["start"], "end": position["end"] }),
+                        None => position.clone(),
+                    });
+                }
+                set_position(&mut inline, position);
+                push_inline(&mut inlines, inline);
+            }
+        }
+        flush_paragraph(&mut blocks, &mut inlines, run_position);
+        blocks
+    }
+
+    fn block(&self, node: &ASTNode) -> Option<Value> {
+        let content = node_value(node, "content");
+        match node_value(node, "type") {
+            "heading" => {
+                let depth: u32 = node_value(node, "level").parse().unwrap_or(1);
+                Some(json!({
+                    "type": "heading",
+                    "depth": depth,
+                    "children": self.inlines(content, false),
+                }))
+            }
+            "hr" => Some(json!({ "type": "thematicBreak" })),
+            "codeBlock" => Some(json!({
+                "type": "code",
+                "lang": optional(node_value(node, "lang")),
+                "meta": null,
+                "value": content,
+            })),
+            "blockQuote" => Some(json!({
+                "type": "blockquote",
+                "children": self.blocks(&self.nested.parse(content, false, true, false), false),
+            })),
+            "list" => {
+                let items: Vec<Value> = list_items(node)
+                    .iter()
+                    .zip(list_checked(node))
+                    .map(|(item, checked)| {
+                        let spread = item.contains("\n\n");
+                        let ast = if spread {
+                            self.nested
+                                .parse(&format!("{}\n\n", item), false, true, false)
+                        } else {
+                            self.nested.parse(item, true, true, false)
+                        };
+                        json!({
+                            "type": "listItem",
+                            "spread": spread,
+                            "checked": checked,
+                            "children": self.blocks(&ast, false),
+                        })
+                    })
+                    .collect();
+                let ordered = node_value(node, "ordered") == "true";
+                let start = if ordered {
+                    json!(node_value(node, "start").parse::<u32>().unwrap_or(1))
+                } else {
+                    Value::Null
+                };
+                Some(json!({
+                    "type": "list",
+                    "ordered": ordered,
+                    "start": start,
+                    "spread": items.iter().any(|item| item["spread"] == true),
+                    "children": items,
+                }))
+            }
+            "table" => {
+                let row = |cells: &[String]| {
+                    let cells: Vec<Value> = cells
+                        .iter()
+                        .map(|cell| json!({ "type": "tableCell", "children": self.inlines(cell, false) }))
+                        .collect();
+                    json!({ "type": "tableRow", "children": cells })
+                };
+                let align: Vec<Value> = table_align(node)
+                    .iter()
+                    .map(|align| optional(align))
+                    .collect();
+                let rows: Vec<Value> = std::iter::once(table_header(node))
+                    .chain(table_cells(node))
+                    .map(|cells| row(&cells))
+                    .collect();
+                Some(json!({ "type": "table", "align": align, "children": rows }))
+            }
+            "paragraph" => Some(json!({
+                "type": "paragraph",
+                "children": self.inlines(content, false),
+            })),
+            "footnoteDef" => Some(self.footnote_definition(node_value(node, "label"), content)),
+            _ => None,
+        }
+    }
+
+    fn footnote_definition(&self, label: &str, content: &str) -> Value {
+        let ast = self
+            .nested
+            .parse(&format!("{}\n\n", content), false, true, false);
+        json!({
+            "type": "footnoteDefinition",
+            "identifier": label,
+            "label": label,
+            "children": self.blocks(&ast, false),
+        })
+    }
+
+    fn inlines(&self, source: &str, in_link: bool) -> Vec<Value> {
+        let mut inlines = Vec::new();
+        for node in self.nested.parse(source, true, true, in_link) {
+            if let Some(inline) = self.inline(&node) {
+                push_inline(&mut inlines, inline);
+            }
+        }
+        trim_text(&mut inlines);
+        inlines
+    }
+
+    fn inline(&self, node: &ASTNode) -> Option<Value> {
+        let content = node_value(node, "content");
+        let parent = |node_type: &str| json!({ "type": node_type, "children": self.inlines(content, false) });
+        let inline = match node_value(node, "type") {
+            "text" => json!({ "type": "text", "value": content }),
+            "em" => parent("emphasis"),
+            "strong" => parent("strong"),
+            "del" => parent("delete"),
+            "inlineCode" => json!({ "type": "inlineCode", "value": content }),
+            "br" => json!({ "type": "break" }),
+            "footnoteRef" => json!({
+                "type": "footnoteReference",
+                "identifier": node_value(node, "label"),
+                "label": node_value(node, "label"),
+            }),
+            "link" => json!({
+                "type": "link",
+                "url": node_value(node, "target"),
+                "title": optional(node_value(node, "title")),
+                "children": self.inlines(content, true),
+            }),
+            "image" => json!({
+                "type": "image",
+                "url": node_value(node, "target"),
+                "title": optional(node_value(node, "title")),
+                "alt": node_value(node, "alt"),
+            }),
+            "" | "def" | "footnoteDef" | "footnotes" | "newline" => return None,
+            // Other fields of custom nodes are kept in `data`
+            node_type => {
+                let data: Map<String, Value> = node
+                    .iter()
+                    .filter(|(key, _)| {
+                        !matches!(key.as_str(), "type" | "content" | "inline" | "position")
+                    })
+                    .map(|(key, value)| (key.clone(), json!(value)))
+                    .collect();
+                let mut custom = json!({ "type": format!("{}{}", MDAST_CUSTOM_PREFIX, node_type) });
+                if !data.is_empty() {
+                    custom["data"] = Value::Object(data);
+                }
+                if node.contains_key("content") {
+                    custom["children"] = json!(self.inlines(content, false));
+                }
+                custom
+            }
+        };
+        Some(inline)
+    }
+}
+
+/// Appends `inline`, merging it into a preceding text node.
+fn push_inline(inlines: &mut Vec<Value>, inline: Value) {
+    if let Some(last) = inlines.last_mut() {
+        if last["type"] == "text" && inline["type"] == "text" {
+            let merged = format!(
+                "{}{}",
+                last["value"].as_str().unwrap_or(""),
+                inline["value"].as_str().unwrap_or("")
+            );
+            last["value"] = json!(merged);
+            if last.get("position").is_some() && inline.get("position").is_some() {
+                last["position"]["end"] = inline["position"]["end"].clone();
+            }
+            return;
+        }
+    }
+    inlines.push(inline);
+}
+
+fn trim_text(inlines: &mut Vec<Value>) {
+    if let Some(first) = inlines.first_mut().filter(|first| first["type"] == "text") {
+        first["value"] = json!(first["value"].as_str().unwrap_or("").trim_start());
+    }
+    if let Some(last) = inlines.last_mut().filter(|last| last["type"] == "text") {
+        last["value"] = json!(last["value"].as_str().unwrap_or("").trim_end());
+    }
+    inlines.retain(|inline| inline["type"] != "text" || inline["value"] != "");
+}
+
+fn flush_paragraph(blocks: &mut Vec<Value>, inlines: &mut Vec<Value>, position: Option<Value>) {
+    trim_text(inlines);
+    if !inlines.is_empty() {
+        let mut paragraph = json!({ "type": "paragraph", "children": std::mem::take(inlines) });
+        set_position(&mut paragraph, position);
+        blocks.push(paragraph);
+    }
+}
+
+/// Converts `ast` to an mdast `root` node, as used by unified and remark.
+///
+/// Nodes mdast has no equivalent for become [`MDAST_CUSTOM_PREFIX`] nodes,
+/// with their other fields in `data`. Positions are exported for ASTs parsed
+/// with [`State::positions`](crate::State::positions).
+pub fn to_mdast(ast: &[ASTNode]) -> Value {
+    let writer = MdastWriter {
+        nested: NestedParser::new(create_default_rules()),
+    };
+    let mut root = json!({ "type": "root", "children": writer.blocks(ast, true) });
+    let positions: Vec<Value> = ast.iter().filter_map(node_position).collect();
+    if let (Some(first), Some(last)) = (positions.first(), positions.last()) {
+        root["position"] = json!({ "start": first["start"], "end": last["end"] });
+    }
+    root
+}
+
+fn str_field<'a>(node: &'a Value, key: &str) -> &'a str {
+    node[key].as_str().unwrap_or("")
+}
+
+fn children(node: &Value) -> &[Value] {
+    node["children"].as_array().map_or(&[], |children| children)
+}
+
+/// Label of a footnote reference or definition, lowercased like the
+/// `footnoteDef` rule does.
+fn footnote_label(node: &Value) -> String {
+    node["label"]
+        .as_str()
+        .unwrap_or(str_field(node, "identifier"))
+        .to_lowercase()
+}
+
+fn link_target(node: &Value) -> String {
+    link_destination(str_field(node, "url"), node["title"].as_str().unwrap_or(""))
+}
+
+fn indent_following_lines(content: &str, width: usize) -> String {
+    let indent = " ".repeat(width);
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prints mdast phrasing content as markdown the default rules parse back.
+fn inline_markdown(nodes: &[Value]) -> String {
+    nodes
+        .iter()
+        .map(|node| {
+            let content = inline_markdown(children(node));
+            match str_field(node, "type") {
+                "text" => escape_markdown(str_field(node, "value")),
+                "emphasis" => format!("_{}_", content),
+                "strong" => format!("**{}**", content),
+                "delete" => format!("~~{}~~", content),
+                "inlineCode" => code_span(str_field(node, "value")),
+                "break" => "  \n".to_string(),
+                "link" => format!("[{}]({})", content, link_target(node)),
+                "image" => format!(
+                    "![{}]({})",
+                    escape_markdown(str_field(node, "alt")),
+                    link_target(node)
+                ),
+                "footnoteReference" => format!("[^{}]", footnote_label(node)),
+                "html" => str_field(node, "value").to_string(),
+                "simpleMarkdown:u" => format!("__{}__", content),
+                "simpleMarkdown:spoiler" => format!("||{}||", content),
+                "simpleMarkdown:mention" | "simpleMarkdown:emoji" => import_node(node)
+                    .map(|mention| to_markdown(&[mention], None))
+                    .unwrap_or_default(),
+                _ => content,
+            }
+        })
+        .collect()
+}
+
+/// Prints mdast flow content as markdown, joining blocks with `separator`.
+fn blocks_markdown(nodes: &[Value], separator: &str) -> String {
+    nodes
+        .iter()
+        .map(|node| match str_field(node, "type") {
+            "heading" => format!(
+                "{} {}",
+                "#".repeat(node["depth"].as_u64().unwrap_or(1) as usize),
+                inline_markdown(children(node))
+            ),
+            "thematicBreak" => "* * *".to_string(),
+            "table" | "footnoteDefinition" => import_node(node)
+                .map(|block| to_markdown(&[block], None))
+                .unwrap_or_default(),
+            "code" => {
+                let fence = "`".repeat(
+                    3.max(
+                        str_field(node, "value")
+                            .split(|c| c != '`')
+                            .map(str::len)
+                            .max()
+                            .unwrap_or(0)
+                            + 1,
+                    ),
+                );
+                format!(
+                    "{}{}\n{}\n{}",
+                    fence,
+                    str_field(node, "lang"),
+                    str_field(node, "value"),
+                    fence
+                )
+            }
+            "blockquote" => blocks_markdown(children(node), "\n\n")
+                .lines()
+                .map(|line| format!("> {}", line).trim_end().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "list" => {
+                let start = node["start"].as_u64().unwrap_or(1);
+                children(node)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let bullet = if node["ordered"] == true {
+                            format!("{}. ", start + i as u64)
+                        } else {
+                            "* ".to_string()
+                        };
+                        format!(
+                            "{}{}{}",
+                            bullet,
+                            task_marker(item),
+                            indent_following_lines(&list_item_markdown(item), bullet.len())
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            _ => inline_markdown(children(node)),
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn list_item_markdown(item: &Value) -> String {
+    let separator = if item["spread"] == true { "\n\n" } else { "\n" };
+    blocks_markdown(children(item), separator)
+}
+
+/// Task list marker of an mdast `listItem`, as the `list` rule parses it.
+fn task_marker(item: &Value) -> &'static str {
+    match item["checked"].as_bool() {
+        Some(true) => "[x] ",
+        Some(false) => "[ ] ",
+        None => "",
+    }
+}
+
+fn import_node(node: &Value) -> Option<ASTNode> {
+    let mut result = ASTNode::new();
+    let mut items = None;
+    let mut set = |key: &str, value: String| {
+        result.insert(key.to_string(), value);
+    };
+    let content = inline_markdown(children(node));
+    match str_field(node, "type") {
+        "heading" => {
+            set("type", "heading".to_string());
+            set("level", node["depth"].as_u64().unwrap_or(1).to_string());
+            set("content", content);
+        }
+        "paragraph" => {
+            set("type", "paragraph".to_string());
+            set("content", content);
+        }
+        "thematicBreak" => set("type", "hr".to_string()),
+        "footnoteDefinition" => {
+            set("type", "footnoteDef".to_string());
+            set("label", footnote_label(node));
+            set("content", blocks_markdown(children(node), "\n\n"));
+        }
+        "code" => {
+            set("type", "codeBlock".to_string());
+            set("lang", str_field(node, "lang").to_string());
+            set("content", str_field(node, "value").to_string());
+        }
+        "blockquote" => {
+            set("type", "blockQuote".to_string());
+            set(
+                "content",
+                format!("{}\n\n", blocks_markdown(children(node), "\n\n")),
+            );
+        }
+        "table" => {
+            let mut rows = children(node).iter().map(|row| {
+                children(row)
+                    .iter()
+                    .map(|cell| inline_markdown(children(cell)))
+                    .collect::<Vec<String>>()
+            });
+            let header = rows.next().unwrap_or_default();
+            let cells: Vec<Vec<String>> = rows
+                .map(|mut row| {
+                    row.resize(header.len(), String::new());
+                    row
+                })
+                .collect();
+            let align: Vec<&str> = node["align"]
+                .as_array()
+                .map_or(&[][..], |align| align)
+                .iter()
+                .map(|align| align.as_str().unwrap_or(""))
+                .collect();
+            set("type", "table".to_string());
+            set("header", serde_json::to_string(&header).unwrap_or_default());
+            set("align", serde_json::to_string(&align).unwrap_or_default());
+            set("cells", serde_json::to_string(&cells).unwrap_or_default());
+        }
+        "list" => {
+            let ordered = node["ordered"] == true;
+            items = Some(
+                children(node)
+                    .iter()
+                    .map(list_item_markdown)
+                    .collect::<Vec<_>>(),
+            );
+            let checked: Vec<Option<bool>> = children(node)
+                .iter()
+                .map(|item| item["checked"].as_bool())
+                .collect();
+            if checked.iter().any(Option::is_some) {
+                set(
+                    "checked",
+                    serde_json::to_string(&checked).unwrap_or_default(),
+                );
+            }
+            set("type", "list".to_string());
+            set("ordered", ordered.to_string());
+            set(
+                "start",
+                if ordered {
+                    node["start"].as_u64().unwrap_or(1).to_string()
+                } else {
+                    String::new()
+                },
+            );
+        }
+        "text" => {
+            set("type", "text".to_string());
+            set("content", str_field(node, "value").to_string());
+        }
+        "emphasis" => {
+            set("type", "em".to_string());
+            set("content", content);
+        }
+        "strong" => {
+            set("type", "strong".to_string());
+            set("content", content);
+        }
+        "delete" => {
+            set("type", "del".to_string());
+            set("content", content);
+        }
+        "inlineCode" => {
+            set("type", "inlineCode".to_string());
+            set("content", str_field(node, "value").to_string());
+        }
+        "break" => set("type", "br".to_string()),
+        "footnoteReference" => {
+            set("type", "footnoteRef".to_string());
+            set("label", footnote_label(node));
+        }
+        "link" => {
+            set("type", "link".to_string());
+            set("content", content);
+            set("target", str_field(node, "url").to_string());
+            set("title", str_field(node, "title").to_string());
+        }
+        "image" => {
+            set("type", "image".to_string());
+            set("alt", str_field(node, "alt").to_string());
+            set("target", str_field(node, "url").to_string());
+            set("title", str_field(node, "title").to_string());
+        }
+        node_type => match node_type.strip_prefix(MDAST_CUSTOM_PREFIX) {
+            Some(custom_type) => {
+                if let Some(data) = node["data"].as_object() {
+                    for (key, value) in data {
+                        if let Some(value) = value.as_str() {
+                            set(key, value.to_string());
+                        }
+                    }
+                }
+                set("type", custom_type.to_string());
+                if node.get("children").is_some() {
+                    set("content", content);
+                }
+            }
+            // Unknown nodes keep their text content
+            None if node.get("children").is_some() => {
+                set("type", "paragraph".to_string());
+                set("content", content);
+            }
+            None => return None,
+        },
+    }
+    if let Some(items) = items {
+        set_list_items(&mut result, &items);
+    }
+    if let Some(position) = node.get("position") {
+        result.insert("position".to_string(), position.to_string());
+    }
+    Some(result)
+}
+
+/// Converts an mdast tree to an AST, the reverse of [`to_mdast`].
+///
+/// Node content is printed back to markdown source, and `position` is kept
+/// when the mdast nodes have one.
+pub fn from_mdast(tree: &Value) -> Vec<ASTNode> {
+    let nodes = if tree["type"] == "root" {
+        children(tree)
+    } else {
+        std::slice::from_ref(tree)
+    };
+
+    let mut result = Vec::new();
+    for node in nodes.iter().filter_map(import_node) {
+        if !result.is_empty() && BLOCK_TYPES.contains(&node_value(&node, "type")) {
+            for _ in 0..2 {
+                result.push(ASTNode::from([("type".to_string(), "newline".to_string())]));
+            }
+        }
+        result.push(node);
+    }
+    result
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use crate::default_rules::ASTNode;
+use crate::output::node_value;
+
+/// Kind of a `mention` node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MentionKind {
+    /// `<@id>`, or `<@!id>` as older clients write it.
+    User,
+    /// `<@&id>`.
+    Role,
+    /// `<#id>`.
+    Channel,
+    /// `@everyone`, which has no ID.
+    Everyone,
+    /// `@here`, which has no ID.
+    Here,
+}
+
+impl MentionKind {
+    /// Name of the kind, as stored in the `kind` field of `mention` nodes and
+    /// used in the `mention-*` class names of the HTML output.
+    pub fn name(self) -> &'static str {
+        match self {
+            MentionKind::User => "user",
+            MentionKind::Role => "role",
+            MentionKind::Channel => "channel",
+            MentionKind::Everyone => "everyone",
+            MentionKind::Here => "here",
+        }
+    }
+
+    /// Returns the kind named `name`, the reverse of [`MentionKind::name`].
+    pub fn from_name(name: &str) -> Option<MentionKind> {
+        match name {
+            "user" => Some(MentionKind::User),
+            "role" => Some(MentionKind::Role),
+            "channel" => Some(MentionKind::Channel),
+            "everyone" => Some(MentionKind::Everyone),
+            "here" => Some(MentionKind::Here),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the IDs of user, role and channel mentions into display names, for
+/// the outputs that take an [`OutputState`](crate::OutputState).
+pub trait MentionResolver {
+    /// Returns the name to show for the mention of `id`, without the leading
+    /// `@` or `#`. Mentions resolved to `None` show their ID instead.
+    fn resolve(&self, kind: MentionKind, id: &str) -> Option<String>;
+}
+
+impl fmt::Debug for dyn MentionResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MentionResolver")
+    }
+}
+
+/// Returns the kind of a `mention` node.
+pub fn mention_kind(node: &ASTNode) -> Option<MentionKind> {
+    MentionKind::from_name(node_value(node, "kind"))
+}
+
+/// Text a `mention` node is shown as, e.g. `@Wumpus` or `#general`.
+pub(crate) fn mention_text(node: &ASTNode, resolver: Option<&dyn MentionResolver>) -> String {
+    let id = node_value(node, "id");
+    let Some(kind) = mention_kind(node) else {
+        return String::new();
+    };
+    let name = || {
+        resolver
+            .and_then(|resolver| resolver.resolve(kind, id))
+            .unwrap_or_else(|| id.to_string())
+    };
+    match kind {
+        MentionKind::User | MentionKind::Role => format!("@{}", name()),
+        MentionKind::Channel => format!("#{}", name()),
+        MentionKind::Everyone | MentionKind::Here => format!("@{}", kind.name()),
+    }
+}
+
+/// Source of a `mention` node, as the `mention` rule parses it.
+pub(crate) fn mention_source(node: &ASTNode) -> String {
+    let id = node_value(node, "id");
+    match mention_kind(node) {
+        Some(MentionKind::User) => format!("<@{}>", id),
+        Some(MentionKind::Role) => format!("<@&{}>", id),
+        Some(MentionKind::Channel) => format!("<#{}>", id),
+        Some(kind) => format!("@{}", kind.name()),
+        None => String::new(),
+    }
+}
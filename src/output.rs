@@ -0,0 +1,336 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::default_rules::{
+    list_item_content, list_item_source, strip_task_marker, ASTNode, RuleMap,
+};
+use crate::footnotes::{footnote_items, Footnote};
+use crate::highlight::Highlighter;
+use crate::mention::MentionResolver;
+use crate::parse::NestedParser;
+
+pub type NestedOutput<'a> = dyn Fn(&str, &mut OutputState) -> String + 'a;
+pub type OutputFunction = fn(&ASTNode, &NestedOutput, &mut OutputState) -> String;
+pub type OutputClosure = Box<dyn Fn(&[ASTNode], Option<OutputState>) -> String>;
+
+/// Options of the outputs, keyed by their type. Outputs read the options they
+/// take, or their defaults if none were set.
+#[derive(Default, Clone)]
+pub struct OutputOptions(HashMap<TypeId, Rc<dyn Any>>);
+
+impl OutputOptions {
+    /// Returns the options with `options` set, replacing any of the same type.
+    pub fn with<T: Any>(mut self, options: T) -> Self {
+        self.set(options);
+        self
+    }
+
+    /// Sets `options`, replacing any of the same type.
+    pub fn set<T: Any>(&mut self, options: T) {
+        self.0.insert(TypeId::of::<T>(), Rc::new(options));
+    }
+
+    /// Returns the options of type `T`, or their defaults if none were set.
+    pub fn get<T: Any + Default>(&self) -> Rc<T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|options| options.clone().downcast::<T>().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// State threaded through every output function while rendering an AST.
+#[derive(Default, Clone)]
+pub struct OutputState {
+    /// Whether nested content is parsed as inline or block content.
+    pub inline: bool,
+    /// Set while outputting link content, so urls inside it are not autolinked again.
+    pub in_link: bool,
+    /// Whether each list whose item is being output is ordered, from the
+    /// outermost. Nested lists are only parsed inside list items.
+    pub lists: Vec<bool>,
+    /// Options of the outputs by type, e.g. `TerminalOptions` for `terminal`.
+    pub options: OutputOptions,
+    /// Highlights code blocks with a language in the `html` and `terminal` outputs.
+    pub highlighter: Option<Rc<dyn Highlighter>>,
+    /// Turns the IDs of `mention` nodes into display names.
+    pub mentions: Option<Rc<dyn MentionResolver>>,
+    /// Footnotes by label, taken from the `footnotes` node of the AST being output.
+    pub footnotes: HashMap<String, Footnote>,
+    /// Number of references output so far to each footnote, by label.
+    pub footnote_references: HashMap<String, usize>,
+    pub data: HashMap<String, String>,
+}
+
+/// Registers the output functions of one format on the rules producing each node type.
+pub(crate) fn register_outputs(
+    rules: &mut RuleMap,
+    format: &str,
+    outputs: Vec<(&str, OutputFunction)>,
+) {
+    for (node_type, output) in outputs {
+        if let Some(rule) = rules.get_mut(node_type) {
+            rule.output.insert(format.to_string(), output);
+        }
+    }
+}
+
+/// Builds an output closure for `format`, dispatching each node to the output
+/// function registered under its `type` in `rules`.
+///
+/// Node content is stored as source text, so the nested output callback passed
+/// to each output function parses that text with the same rules before
+/// outputting it. Nodes without an output function for `format` output nothing.
+pub fn output_for(rules: RuleMap, format: &str) -> OutputClosure {
+    let outputs: HashMap<String, OutputFunction> = rules
+        .iter()
+        .filter_map(|(rule_type, rule)| {
+            rule.output
+                .get(format)
+                .map(|output| (rule_type.clone(), *output))
+        })
+        .collect();
+    let nested = NestedParser::new(rules);
+
+    Box::new(
+        move |ast: &[ASTNode], state: Option<OutputState>| -> String {
+            let mut state = state.unwrap_or_default();
+            // References are parsed from node content, so they can only find
+            // their footnote through the state
+            if let Some(section) = ast
+                .iter()
+                .find(|node| node_value(node, "type") == "footnotes")
+            {
+                state.footnotes = footnote_items(section)
+                    .into_iter()
+                    .map(|footnote| (footnote.label.clone(), footnote))
+                    .collect();
+            }
+            output_nodes(
+                ast,
+                &outputs,
+                &|source: &str, state: &OutputState| {
+                    nested.parse(source, state.inline, !state.lists.is_empty(), state.in_link)
+                },
+                &mut state,
+            )
+        },
+    )
+}
+
+fn output_nodes(
+    ast: &[ASTNode],
+    outputs: &HashMap<String, OutputFunction>,
+    parse: &dyn Fn(&str, &OutputState) -> Vec<ASTNode>,
+    state: &mut OutputState,
+) -> String {
+    let nested = |source: &str, state: &mut OutputState| -> String {
+        let nodes = parse(source, state);
+        output_nodes(&nodes, outputs, parse, state)
+    };
+
+    let mut result = String::new();
+    for node in ast {
+        let output = node
+            .get("type")
+            .and_then(|node_type| outputs.get(node_type));
+        if let Some(output) = output {
+            result.push_str(&output(node, &nested, state));
+        }
+    }
+    result
+}
+
+/// Outputs `source` parsed as inline content.
+pub fn output_inline(nested: &NestedOutput, source: &str, state: &mut OutputState) -> String {
+    let old_inline = state.inline;
+    state.inline = true;
+    let result = nested(source, state);
+    state.inline = old_inline;
+    result
+}
+
+/// Outputs `source` parsed as block content.
+pub fn output_block(nested: &NestedOutput, source: &str, state: &mut OutputState) -> String {
+    let old_inline = state.inline;
+    state.inline = false;
+    let result = nested(source, state);
+    state.inline = old_inline;
+    result
+}
+
+/// Outputs the content of a `link` node without autolinking urls inside it.
+pub fn output_link_content(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    state: &mut OutputState,
+) -> String {
+    let old_in_link = state.in_link;
+    state.in_link = true;
+    let result = output_inline(nested, node_value(node, "content"), state);
+    state.in_link = old_in_link;
+    result
+}
+
+/// Outputs an item of the `list` node `node`, as block content if it spans
+/// several paragraphs.
+pub fn output_list_item(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    item: &str,
+    state: &mut OutputState,
+) -> String {
+    state.lists.push(node_value(node, "ordered") == "true");
+    let result = if item.contains("\n\n") {
+        output_block(nested, &format!("{}\n\n", item), state)
+    } else {
+        output_inline(nested, item, state)
+    };
+    state.lists.pop();
+    result
+}
+
+/// Returns the value of `key` on `node`, or an empty string if it is missing.
+pub fn node_value<'a>(node: &'a ASTNode, key: &str) -> &'a str {
+    node.get(key).map_or("", |value| value.as_str())
+}
+
+/// Returns the source of the items of a `list` node, with their bullets.
+pub(crate) fn list_item_sources(node: &ASTNode) -> Vec<String> {
+    node.get("items")
+        .and_then(|items| serde_json::from_str(items).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the content of the items of a `list` node, without their bullets
+/// and task list markers.
+pub fn list_items(node: &ASTNode) -> Vec<String> {
+    list_item_sources(node)
+        .iter()
+        .zip(list_checked(node))
+        .map(|(item, checked)| {
+            let content = list_item_content(item);
+            match checked {
+                Some(_) => strip_task_marker(&content).to_string(),
+                None => content,
+            }
+        })
+        .collect()
+}
+
+/// Returns whether each item of a `list` node is a checked or unchecked task
+/// list item, or `None` for items that aren't tasks.
+pub fn list_checked(node: &ASTNode) -> Vec<Option<bool>> {
+    let mut checked: Vec<Option<bool>> = node
+        .get("checked")
+        .and_then(|checked| serde_json::from_str(checked).ok())
+        .unwrap_or_default();
+    checked.resize(list_item_sources(node).len(), None);
+    checked
+}
+
+/// Sets the items of a `list` node to `items`, adding the bullets and the
+/// task list markers of its `ordered`, `start` and `checked` fields.
+pub(crate) fn set_list_items(node: &mut ASTNode, items: &[String]) {
+    let ordered = node_value(node, "ordered") == "true";
+    let start: usize = node_value(node, "start").parse().unwrap_or(1);
+    let mut checked: Vec<Option<bool>> = node
+        .get("checked")
+        .and_then(|checked| serde_json::from_str(checked).ok())
+        .unwrap_or_default();
+    checked.resize(items.len(), None);
+    let sources: Vec<String> = items
+        .iter()
+        .zip(checked)
+        .enumerate()
+        .map(|(i, (item, checked))| {
+            let bullet = if ordered {
+                format!("{}. ", start + i)
+            } else {
+                "* ".to_string()
+            };
+            list_item_source(&bullet, task_marker(checked, "[ ] ", "[x] "), item)
+        })
+        .collect();
+    node.insert(
+        "items".to_string(),
+        serde_json::to_string(&sources).unwrap_or_default(),
+    );
+}
+
+/// Returns `unchecked` or `done` for task list items, or an empty string
+/// for other items.
+pub(crate) fn task_marker<'a>(checked: Option<bool>, unchecked: &'a str, done: &'a str) -> &'a str {
+    match checked {
+        Some(true) => done,
+        Some(false) => unchecked,
+        None => "",
+    }
+}
+
+/// Returns the source of the header cells of a `table` node.
+pub fn table_header(node: &ASTNode) -> Vec<String> {
+    node.get("header")
+        .and_then(|header| serde_json::from_str(header).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the alignment of each column of a `table` node: `"left"`,
+/// `"center"`, `"right"`, or an empty string if it isn't aligned.
+pub fn table_align(node: &ASTNode) -> Vec<String> {
+    node.get("align")
+        .and_then(|align| serde_json::from_str(align).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the source of the cells of each body row of a `table` node.
+pub fn table_cells(node: &ASTNode) -> Vec<Vec<String>> {
+    node.get("cells")
+        .and_then(|cells| serde_json::from_str(cells).ok())
+        .unwrap_or_default()
+}
+
+/// Pads `text` to `width` columns according to a column alignment of a
+/// `table` node. `text_width` is the number of columns `text` takes up.
+pub(crate) fn align_cell(text: &str, text_width: usize, width: usize, align: &str) -> String {
+    let padding = width.saturating_sub(text_width);
+    let (left, right) = match align {
+        "right" => (padding, 0),
+        "center" => (padding / 2, padding - padding / 2),
+        _ => (0, padding),
+    };
+    format!("{}{}{}", " ".repeat(left), text, " ".repeat(right))
+}
+
+/// Outputs the cells of a `table` node as inline content, with the header
+/// cells as the first row.
+pub fn output_table_rows(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    state: &mut OutputState,
+) -> Vec<Vec<String>> {
+    std::iter::once(table_header(node))
+        .chain(table_cells(node))
+        .map(|row| {
+            row.iter()
+                .map(|cell| output_inline(nested, cell, state))
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the width of each column of `rows`, as measured by `width`.
+pub(crate) fn column_widths(rows: &[Vec<String>], width: fn(&str) -> usize) -> Vec<usize> {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (column, cell) in row.iter().enumerate() {
+            let cell_width = width(cell);
+            match widths.get_mut(column) {
+                Some(max) => *max = (*max).max(cell_width),
+                None => widths.push(cell_width),
+            }
+        }
+    }
+    widths
+}
@@ -0,0 +1,324 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::{emoji_text, emoji_url, EmojiOptions};
+use crate::footnotes::{footnote_items, reference_source, Footnote};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, table_align, table_cells, table_header, OutputState,
+};
+use crate::parse::NestedParser;
+
+/// Version of the pandoc-types API the exported JSON follows.
+pub const PANDOC_API_VERSION: [u32; 3] = [1, 23, 1];
+
+const BLOCK_TYPES: [&str; 11] = [
+    "heading",
+    "hr",
+    "codeBlock",
+    "blockQuote",
+    "list",
+    "table",
+    "paragraph",
+    "def",
+    "footnoteDef",
+    "footnotes",
+    "newline",
+];
+
+fn attr(classes: &[&str]) -> Value {
+    json!(["", classes, []])
+}
+
+fn element(t: &str, c: Value) -> Value {
+    json!({ "t": t, "c": c })
+}
+
+fn is_space(inline: &Value) -> bool {
+    matches!(inline["t"].as_str(), Some("Space" | "SoftBreak"))
+}
+
+struct PandocWriter {
+    nested: NestedParser,
+    state: OutputState,
+    footnotes: HashMap<String, Footnote>,
+}
+
+impl PandocWriter {
+    /// Converts `ast` to blocks, wrapping runs of inline nodes in `wrapper`
+    /// (`Para` or `Plain`).
+    fn blocks(&self, ast: &[ASTNode], wrapper: &str) -> Vec<Value> {
+        let mut blocks = Vec::new();
+        let mut inlines = Vec::new();
+        for node in ast {
+            if BLOCK_TYPES.contains(&node_value(node, "type")) {
+                flush_inlines(&mut blocks, &mut inlines, wrapper);
+                blocks.extend(self.block(node));
+            } else {
+                push_inlines(&mut inlines, self.inline(node));
+            }
+        }
+        flush_inlines(&mut blocks, &mut inlines, wrapper);
+        blocks
+    }
+
+    fn block(&self, node: &ASTNode) -> Option<Value> {
+        let content = node_value(node, "content");
+        match node_value(node, "type") {
+            "heading" => {
+                let level: u32 = node_value(node, "level").parse().unwrap_or(1);
+                Some(element(
+                    "Header",
+                    json!([level, attr(&[]), self.inlines(content, false)]),
+                ))
+            }
+            "hr" => Some(json!({ "t": "HorizontalRule" })),
+            "codeBlock" => {
+                let lang = node_value(node, "lang");
+                let classes: Vec<&str> = if lang.is_empty() { vec![] } else { vec![lang] };
+                Some(element("CodeBlock", json!([attr(&classes), content])))
+            }
+            "blockQuote" => Some(element(
+                "BlockQuote",
+                Value::Array(self.blocks(&self.nested.parse(content, false, true, false), "Para")),
+            )),
+            "list" => {
+                let items: Vec<Value> = list_items(node)
+                    .iter()
+                    .zip(list_checked(node))
+                    .map(|(item, checked)| {
+                        let ast = if item.contains("\n\n") {
+                            self.nested
+                                .parse(&format!("{}\n\n", item), false, true, false)
+                        } else {
+                            self.nested.parse(item, true, true, false)
+                        };
+                        let mut blocks = self.blocks(&ast, "Plain");
+                        if let Some(checked) = checked {
+                            add_task_box(&mut blocks, checked);
+                        }
+                        Value::Array(blocks)
+                    })
+                    .collect();
+                if node_value(node, "ordered") == "true" {
+                    let start: u32 = node_value(node, "start").parse().unwrap_or(1);
+                    let attributes = json!([start, { "t": "Decimal" }, { "t": "Period" }]);
+                    Some(element("OrderedList", json!([attributes, items])))
+                } else {
+                    Some(element("BulletList", Value::Array(items)))
+                }
+            }
+            "table" => {
+                let alignment = |align: &str| match align {
+                    "left" => json!({ "t": "AlignLeft" }),
+                    "center" => json!({ "t": "AlignCenter" }),
+                    "right" => json!({ "t": "AlignRight" }),
+                    _ => json!({ "t": "AlignDefault" }),
+                };
+                let colspecs: Vec<Value> = table_align(node)
+                    .iter()
+                    .map(|align| json!([alignment(align), { "t": "ColWidthDefault" }]))
+                    .collect();
+                let row = |cells: &[String]| {
+                    let cells: Vec<Value> = cells
+                        .iter()
+                        .map(|cell| {
+                            let blocks = json!([element("Plain", self.inlines(cell, false))]);
+                            json!([attr(&[]), alignment(""), 1, 1, blocks])
+                        })
+                        .collect();
+                    json!([attr(&[]), cells])
+                };
+                let rows: Vec<Value> = table_cells(node).iter().map(|cells| row(cells)).collect();
+                Some(element(
+                    "Table",
+                    json!([
+                        attr(&[]),
+                        [null, []],
+                        colspecs,
+                        [attr(&[]), [row(&table_header(node))]],
+                        [[attr(&[]), 0, [], rows]],
+                        [attr(&[]), []],
+                    ]),
+                ))
+            }
+            "paragraph" => Some(element("Para", self.inlines(content, false))),
+            _ => None,
+        }
+    }
+
+    fn inlines(&self, source: &str, in_link: bool) -> Value {
+        let mut inlines = Vec::new();
+        for node in self.nested.parse(source, true, true, in_link) {
+            push_inlines(&mut inlines, self.inline(&node));
+        }
+        trim_spaces(&mut inlines);
+        Value::Array(inlines)
+    }
+
+    fn inline(&self, node: &ASTNode) -> Vec<Value> {
+        let content = node_value(node, "content");
+        let target = json!([node_value(node, "target"), node_value(node, "title")]);
+        let inline = match node_value(node, "type") {
+            "text" => return text(content),
+            "em" => element("Emph", self.inlines(content, false)),
+            "strong" => element("Strong", self.inlines(content, false)),
+            "u" => element("Underline", self.inlines(content, false)),
+            "del" => element("Strikeout", self.inlines(content, false)),
+            "inlineCode" => element("Code", json!([attr(&[]), content])),
+            "br" => json!({ "t": "LineBreak" }),
+            "link" => element(
+                "Link",
+                json!([attr(&[]), self.inlines(content, true), target]),
+            ),
+            "image" => element(
+                "Image",
+                json!([attr(&[]), text(node_value(node, "alt")), target]),
+            ),
+            // Pandoc places notes itself, so each reference carries its content
+            "footnoteRef" => match self.footnotes.get(node_value(node, "label")) {
+                Some(footnote) => {
+                    let source = format!("{}\n\n", footnote.content);
+                    let blocks =
+                        self.blocks(&self.nested.parse(&source, false, true, false), "Para");
+                    element("Note", Value::Array(blocks))
+                }
+                None => return text(&reference_source(node)),
+            },
+            "emoji" => {
+                let name = emoji_text(node);
+                let url = emoji_url(node, &self.state.options.get::<EmojiOptions>());
+                element("Image", json!([attr(&["emoji"]), text(&name), [url, name]]))
+            }
+            "mention" => {
+                let kind = format!("mention-{}", node_value(node, "kind"));
+                let id = node_value(node, "id");
+                let attributes: Vec<[&str; 2]> = if id.is_empty() {
+                    vec![]
+                } else {
+                    vec![["data-id", id]]
+                };
+                element(
+                    "Span",
+                    json!([
+                        ["", ["mention", kind], attributes],
+                        text(&mention_text(node, self.state.mentions.as_deref()))
+                    ]),
+                )
+            }
+            // Nodes pandoc has no element for keep their type as a span class
+            node_type => element(
+                "Span",
+                json!([attr(&[node_type]), self.inlines(content, false)]),
+            ),
+        };
+        vec![inline]
+    }
+}
+
+/// Starts the content of a task list item with a ballot box, as pandoc's
+/// `task_lists` extension does.
+fn add_task_box(blocks: &mut Vec<Value>, checked: bool) {
+    let ballot_box = if checked { "☒" } else { "☐" };
+    let marker = [element("Str", json!(ballot_box)), json!({ "t": "Space" })];
+    match blocks.first_mut() {
+        Some(first) if matches!(first["t"].as_str(), Some("Plain" | "Para")) => {
+            if let Some(inlines) = first["c"].as_array_mut() {
+                inlines.splice(0..0, marker);
+            }
+        }
+        _ => blocks.insert(0, element("Plain", json!([marker[0]]))),
+    }
+}
+
+/// Splits text into pandoc `Str`, `Space` and `SoftBreak` inlines.
+fn text(content: &str) -> Vec<Value> {
+    let mut inlines = Vec::new();
+    let mut word = String::new();
+    for c in content.chars() {
+        if c == ' ' || c == '\n' {
+            if !word.is_empty() {
+                inlines.push(element("Str", json!(std::mem::take(&mut word))));
+            }
+            let space = if c == '\n' {
+                json!({ "t": "SoftBreak" })
+            } else {
+                json!({ "t": "Space" })
+            };
+            // Collapse runs of whitespace, keeping a soft break over a space
+            match inlines.last_mut() {
+                Some(last) if is_space(last) => {
+                    if c == '\n' {
+                        *last = space;
+                    }
+                }
+                _ => inlines.push(space),
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        inlines.push(element("Str", json!(word)));
+    }
+    inlines
+}
+
+/// Appends `new` to `inlines`, merging adjacent `Str` and whitespace inlines.
+fn push_inlines(inlines: &mut Vec<Value>, new: Vec<Value>) {
+    for inline in new {
+        match inlines.last_mut() {
+            Some(last) if last["t"] == "Str" && inline["t"] == "Str" => {
+                let merged = format!(
+                    "{}{}",
+                    last["c"].as_str().unwrap_or(""),
+                    inline["c"].as_str().unwrap_or("")
+                );
+                last["c"] = json!(merged);
+            }
+            Some(last) if is_space(last) && is_space(&inline) => {
+                if inline["t"] == "SoftBreak" {
+                    *last = inline;
+                }
+            }
+            _ => inlines.push(inline),
+        }
+    }
+}
+
+fn trim_spaces(inlines: &mut Vec<Value>) {
+    while inlines.last().is_some_and(is_space) {
+        inlines.pop();
+    }
+    while inlines.first().is_some_and(is_space) {
+        inlines.remove(0);
+    }
+}
+
+fn flush_inlines(blocks: &mut Vec<Value>, inlines: &mut Vec<Value>, wrapper: &str) {
+    trim_spaces(inlines);
+    if !inlines.is_empty() {
+        blocks.push(element(wrapper, Value::Array(std::mem::take(inlines))));
+    }
+}
+
+/// Exports `ast` as a pandoc JSON document, readable with `pandoc -f json`.
+pub fn to_pandoc_json(ast: &[ASTNode], state: Option<OutputState>) -> Value {
+    let footnotes = ast
+        .iter()
+        .filter(|node| node_value(node, "type") == "footnotes")
+        .flat_map(footnote_items)
+        .map(|footnote| (footnote.label.clone(), footnote))
+        .collect();
+    let writer = PandocWriter {
+        nested: NestedParser::new(create_default_rules()),
+        state: state.unwrap_or_default(),
+        footnotes,
+    };
+    json!({
+        "pandoc-api-version": PANDOC_API_VERSION,
+        "meta": {},
+        "blocks": writer.blocks(ast, "Para"),
+    })
+}
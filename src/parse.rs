@@ -1,72 +1,146 @@
-use fancy_regex::Regex;
-
-use crate::default_rules::{ASTNode, ParserClosure, RuleMap, State};
-
-lazy_static::lazy_static! {
-    static ref CR_NEWLINE_R: Regex = Regex::new(r"\r\n?").unwrap();
-    static ref TAB_R: Regex = Regex::new(r"\t").unwrap();
-    static ref FORMFEED_R: Regex = Regex::new(r"\f").unwrap();
-}
-
-pub(crate) fn preprocess(source: &str) -> String {
-    let result = CR_NEWLINE_R.replace_all(source, "\n");
-    let result = FORMFEED_R.replace_all(&result, "");
-    TAB_R.replace_all(&result, "    ").to_string()
-}
-
-pub fn parser_for(rules: RuleMap) -> ParserClosure {
-    let mut rule_list: Vec<_> = rules
-        .into_iter() // Transfer ownership of the rules into the iteration
-        .filter(|(_, rule)| rule.match_fn.is_some())
-        .collect();
-
-    // Sort rules by order
-    rule_list.sort_by(|(_, rule_a), (_, rule_b)| {
-        let order_a = rule_a.order.unwrap_or(f64::MAX);
-        let order_b = rule_b.order.unwrap_or(f64::MAX);
-        order_a.partial_cmp(&order_b).unwrap()
-    });
-
-    // Return a boxed closure
-    Box::new(move |source: &str, state: Option<State>| -> Vec<ASTNode> {
-        let mut state = state.unwrap_or_default();
-        let mut result = Vec::new();
-        let mut remaining_source = preprocess(source);
-
-        while !remaining_source.is_empty() {
-            let mut matched = false;
-
-            for (_rule_type, rule) in &rule_list {
-                if let Some(match_fn) = rule.match_fn {
-                    if let Some(capture) = match_fn(&remaining_source, &mut state) {
-                        let matched_len = if let Some(capture_len) = rule.capture_len {
-                            capture_len(&capture)
-                        } else if !capture.is_empty() {
-                            capture[0].len()
-                        } else {
-                            1
-                        };
-
-                        remaining_source = remaining_source[matched_len..].to_string();
-
-                        if let Some(parse_fn) = rule.parse {
-                            let parsed = parse_fn(capture, &mut state);
-                            if !parsed.is_empty() {
-                                result.push(parsed);
-                            }
-                        }
-                        matched = true;
-                        break;
-                    }
-                }
-            }
-
-            if !matched {
-                // Take at least one character if nothing matches to avoid infinite loop
-                remaining_source = remaining_source[1..].to_string();
-            }
-        }
-
-        result
-    })
-}
+use fancy_regex::Regex;
+
+use crate::default_rules::{ASTNode, ParserClosure, RuleMap, State};
+
+lazy_static::lazy_static! {
+    static ref CR_NEWLINE_R: Regex = Regex::new(r"\r\n?").unwrap();
+    static ref TAB_R: Regex = Regex::new(r"\t").unwrap();
+    static ref FORMFEED_R: Regex = Regex::new(r"\f").unwrap();
+}
+
+/// A point in the preprocessed source, with 1-based line and column as in unist.
+#[derive(Clone, Copy)]
+struct Point {
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+impl Point {
+    fn advance(&mut self, consumed: &str) {
+        self.offset += consumed.len();
+        match consumed.rfind('\n') {
+            Some(last_newline) => {
+                self.line += consumed.matches('\n').count();
+                self.column = consumed[last_newline + 1..].chars().count() + 1;
+            }
+            None => self.column += consumed.chars().count(),
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({ "line": self.line, "column": self.column, "offset": self.offset })
+    }
+}
+
+pub(crate) fn preprocess(source: &str) -> String {
+    let result = CR_NEWLINE_R.replace_all(source, "\n");
+    let result = FORMFEED_R.replace_all(&result, "");
+    TAB_R.replace_all(&result, "    ").to_string()
+}
+
+pub fn parser_for(rules: RuleMap) -> ParserClosure {
+    let mut rule_list: Vec<_> = rules
+        .into_iter() // Transfer ownership of the rules into the iteration
+        .filter(|(_, rule)| rule.match_fn.is_some())
+        .collect();
+
+    // Sort rules by order
+    rule_list.sort_by(|(_, rule_a), (_, rule_b)| {
+        let order_a = rule_a.order.unwrap_or(f64::MAX);
+        let order_b = rule_b.order.unwrap_or(f64::MAX);
+        order_a.partial_cmp(&order_b).unwrap()
+    });
+
+    // Return a boxed closure. With `State::positions`, each node gets its span
+    // in the preprocessed source as a JSON-encoded unist `position`.
+    Box::new(move |source: &str, state: Option<State>| -> Vec<ASTNode> {
+        let mut state = state.unwrap_or_default();
+        let mut result = Vec::new();
+        let mut remaining_source = preprocess(source);
+        let mut point = Point {
+            line: 1,
+            column: 1,
+            offset: 0,
+        };
+
+        while !remaining_source.is_empty() {
+            let mut matched = false;
+
+            for (_rule_type, rule) in &rule_list {
+                if let Some(match_fn) = rule.match_fn {
+                    if let Some(capture) = match_fn(&remaining_source, &mut state) {
+                        let matched_len = if let Some(capture_len) = rule.capture_len {
+                            capture_len(&capture)
+                        } else if !capture.is_empty() {
+                            capture[0].len()
+                        } else {
+                            1
+                        };
+
+                        let consumed = remaining_source[..matched_len].to_string();
+                        remaining_source = remaining_source[matched_len..].to_string();
+                        let start = point;
+                        point.advance(&consumed);
+
+                        if let Some(parse_fn) = rule.parse {
+                            let mut parsed = parse_fn(capture, &mut state);
+                            if !parsed.is_empty() {
+                                if state.positions {
+                                    let position = serde_json::json!({
+                                        "start": start.to_json(),
+                                        "end": point.to_json(),
+                                    });
+                                    parsed.insert("position".to_string(), position.to_string());
+                                }
+                                result.push(parsed);
+                            }
+                        }
+                        // Rules like `list` look behind at what the previous rule consumed
+                        state.prev_capture = Some(consumed);
+                        matched = true;
+                        break;
+                    }
+                }
+            }
+
+            if !matched {
+                // Take at least one character if nothing matches to avoid infinite loop
+                point.advance(&remaining_source[..1]);
+                remaining_source = remaining_source[1..].to_string();
+            }
+        }
+
+        result
+    })
+}
+
+/// Parses the content of nodes, which is kept as source text, with the rules
+/// their AST was parsed with.
+pub(crate) struct NestedParser(ParserClosure);
+
+impl NestedParser {
+    pub(crate) fn new(rules: RuleMap) -> Self {
+        NestedParser(parser_for(rules))
+    }
+
+    /// Parses `source` as inline or block content, as found inside a list
+    /// item if `in_list` and inside link text if `in_link`.
+    pub(crate) fn parse(
+        &self,
+        source: &str,
+        inline: bool,
+        in_list: bool,
+        in_link: bool,
+    ) -> Vec<ASTNode> {
+        (self.0)(
+            source,
+            Some(State {
+                inline,
+                _list: in_list,
+                in_link,
+                ..Default::default()
+            }),
+        )
+    }
+}
@@ -0,0 +1,164 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_paragraphs, next_reference, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    align_cell, column_widths, list_checked, list_items, node_value, output_block, output_for,
+    output_inline, output_link_content, output_list_item, output_table_rows, table_align,
+    task_marker, OutputFunction, OutputState,
+};
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Choices made by `to_plain_text` for nodes that have no plain text equivalent.
+#[derive(Debug, Clone)]
+pub struct PlainTextOptions {
+    /// Output links as `text (url)` instead of only their text.
+    pub link_urls: bool,
+    /// Keep the content of code blocks instead of dropping them.
+    pub code_blocks: bool,
+    /// Prefix of unordered list items.
+    pub bullet: String,
+    /// Replaces the content of spoilers, so it isn't given away.
+    pub spoiler_mask: String,
+}
+
+impl Default for PlainTextOptions {
+    fn default() -> Self {
+        PlainTextOptions {
+            link_urls: true,
+            code_blocks: true,
+            bullet: "-".to_string(),
+            spoiler_mask: "████".to_string(),
+        }
+    }
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("hr", |_node, _nested, _state| "\n\n".to_string()),
+        ("codeBlock", |node, _nested, state| {
+            if state.options.get::<PlainTextOptions>().code_blocks {
+                format!("{}\n\n", node_value(node, "content"))
+            } else {
+                String::new()
+            }
+        }),
+        ("blockQuote", |node, nested, state| {
+            output_block(nested, node_value(node, "content"), state)
+        }),
+        ("list", |node, nested, state| {
+            let ordered = node_value(node, "ordered") == "true";
+            let start: usize = node_value(node, "start").parse().unwrap_or(1);
+            let mut result = String::new();
+            let checked = list_checked(node);
+            for (i, item) in list_items(node).iter().enumerate() {
+                let bullet = if ordered {
+                    format!("{}.", start + i)
+                } else {
+                    state.options.get::<PlainTextOptions>().bullet.clone()
+                };
+                let content = output_list_item(node, nested, item, state);
+                let task = task_marker(checked[i], "[ ] ", "[x] ");
+                result.push_str(&format!("{} {}{}\n", bullet, task, content.trim_end()));
+            }
+            result.push('\n');
+            result
+        }),
+        ("table", |node, nested, state| {
+            let rows = output_table_rows(node, nested, state);
+            let align = table_align(node);
+            let widths = column_widths(&rows, |cell| cell.chars().count());
+            let line = |row: &Vec<String>| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .enumerate()
+                    .map(|(column, cell)| {
+                        let align = align.get(column).map_or("", String::as_str);
+                        align_cell(cell, cell.chars().count(), widths[column], align)
+                    })
+                    .collect();
+                cells.join(" | ").trim_end().to_string()
+            };
+            let mut lines: Vec<String> = rows.iter().map(line).collect();
+            let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+            lines.insert(1, rule.join("-+-"));
+            format!("{}\n\n", lines.join("\n"))
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_paragraphs(node, nested, state, |number| format!("[{}]", number))
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            let target = node_value(node, "target");
+            let shows_target =
+                target == content || target.strip_prefix("mailto:") == Some(content.as_str());
+            if state.options.get::<PlainTextOptions>().link_urls
+                && !target.is_empty()
+                && !shows_target
+            {
+                format!("{} ({})", content, target)
+            } else {
+                content
+            }
+        }),
+        ("image", |node, _nested, _state| {
+            node_value(node, "alt").to_string()
+        }),
+        ("em", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("strong", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("u", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("del", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("spoiler", |_node, _nested, state| {
+            state.options.get::<PlainTextOptions>().spoiler_mask.clone()
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            node_value(node, "content").to_string()
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("[{}]", footnote.number),
+                None => reference_source(node),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n".to_string()),
+        ("mention", |node, _nested, state| {
+            mention_text(node, state.mentions.as_deref())
+        }),
+        ("emoji", |node, _nested, _state| emoji_text(node)),
+        ("text", |node, _nested, _state| {
+            node_value(node, "content").to_string()
+        }),
+    ]
+}
+
+/// Outputs `ast` as plain text, stripping all formatting. Takes its
+/// [`PlainTextOptions`] from `state`.
+pub fn to_plain_text(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "text")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,249 @@
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_items, next_reference, output_footnote, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, table_align, OutputFunction,
+    OutputState,
+};
+
+/// Fields of the `.TH` title line written by `to_roff`.
+#[derive(Debug, Clone, Default)]
+pub struct RoffOptions {
+    /// Name of the page, e.g. `TOOL`. No `.TH` line is written when empty.
+    pub title: String,
+    /// Manual section, e.g. `1`.
+    pub section: String,
+    pub date: String,
+    pub source: String,
+    pub manual: String,
+}
+
+/// Escapes text so roff doesn't read it as escapes or requests.
+pub fn escape_roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    let escaped = escaped.replace("\n.", "\n\\&.").replace("\n'", "\n\\&'");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Outputs `content` in `font`, added to the font of the text around it so
+/// nested emphasis is bold italic, then switches back to that font.
+fn font(
+    state: &mut OutputState,
+    font: char,
+    content: impl FnOnce(&mut OutputState) -> String,
+) -> String {
+    let outer = state
+        .data
+        .get("roff_font")
+        .cloned()
+        .unwrap_or_else(|| "R".to_string());
+    let bold = outer.contains('B') || font == 'B';
+    let italic = outer.contains('I') || font == 'I';
+    let inner = match (bold, italic) {
+        (true, true) => "BI",
+        (true, false) => "B",
+        (false, true) => "I",
+        (false, false) => "R",
+    };
+    state
+        .data
+        .insert("roff_font".to_string(), inner.to_string());
+    let content = content(state);
+    state.data.insert("roff_font".to_string(), outer.clone());
+    format!("{}{}{}", font_escape(inner), content, font_escape(&outer))
+}
+
+fn font_escape(font: &str) -> String {
+    if font.len() == 2 {
+        format!("\\f({}", font)
+    } else {
+        format!("\\f{}", font)
+    }
+}
+
+fn quote_argument(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\\(dq"))
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            let request = if node_value(node, "level") == "1" {
+                ".SH"
+            } else {
+                ".SS"
+            };
+            format!("{} {}\n", request, content.replace('\n', " "))
+        }),
+        ("hr", |_node, _nested, _state| ".sp\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            format!(
+                ".PP\n.RS 4\n.nf\n{}\n.fi\n.RE\n",
+                escape_roff(node_value(node, "content"))
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            format!(".RS\n{}.RE\n", content)
+        }),
+        ("list", |node, nested, state| {
+            let ordered = node_value(node, "ordered") == "true";
+            let start: usize = node_value(node, "start").parse().unwrap_or(1);
+            let mut result = String::new();
+            let checked = list_checked(node);
+            for (i, item) in list_items(node).iter().enumerate() {
+                let tag = match checked[i] {
+                    Some(true) => "[x] 4".to_string(),
+                    Some(false) => "[\\ ] 4".to_string(),
+                    None if ordered => format!("{}. 4", start + i),
+                    None => "\\(bu 2".to_string(),
+                };
+                let content = output_list_item(node, nested, item, state);
+                result.push_str(&format!(".IP {}\n{}\n", tag, content.trim_end()));
+            }
+            if !state.lists.is_empty() {
+                format!(".RS\n{}.RE\n", result)
+            } else {
+                result
+            }
+        }),
+        // Tables are laid out by the tbl preprocessor, with tab separated cells
+        ("table", |node, nested, state| {
+            let columns: Vec<&str> = table_align(node)
+                .iter()
+                .map(|align| match align.as_str() {
+                    "center" => "c",
+                    "right" => "r",
+                    _ => "l",
+                })
+                .collect();
+            let header_format: Vec<String> = columns
+                .iter()
+                .map(|column| format!("{}b", column))
+                .collect();
+            let rows: Vec<String> = output_table_rows(node, nested, state)
+                .iter()
+                .map(|row| row.join("\t"))
+                .collect();
+            format!(
+                ".PP\n.TS\n{}\n{}.\n{}\n.TE\n",
+                header_format.join(" "),
+                columns.join(" "),
+                rows.join("\n")
+            )
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_items(node)
+                .iter()
+                .map(|footnote| {
+                    let content = output_footnote(nested, footnote, state);
+                    format!(
+                        ".IP [{}] 4\n{}\n",
+                        footnote.number,
+                        content.trim().trim_start_matches(".PP\n")
+                    )
+                })
+                .collect()
+        }),
+        ("newline", |_node, _nested, _state| String::new()),
+        ("paragraph", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            format!(".PP\n{}\n", content.trim_end())
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            let target = node_value(node, "target");
+            if target.is_empty()
+                || target == content
+                || target.strip_prefix("mailto:") == Some(content.as_str())
+            {
+                content
+            } else {
+                format!("{} <{}>", content, escape_roff(target))
+            }
+        }),
+        ("image", |node, _nested, _state| {
+            escape_roff(node_value(node, "alt"))
+        }),
+        ("em", |node, nested, state| {
+            font(state, 'I', |state| {
+                output_inline(nested, node_value(node, "content"), state)
+            })
+        }),
+        ("strong", |node, nested, state| {
+            font(state, 'B', |state| {
+                output_inline(nested, node_value(node, "content"), state)
+            })
+        }),
+        ("u", |node, nested, state| {
+            font(state, 'I', |state| {
+                output_inline(nested, node_value(node, "content"), state)
+            })
+        }),
+        ("del", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        // Man pages can't hide text, so spoilers keep their content
+        ("spoiler", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("inlineCode", |node, _nested, state| {
+            font(state, 'B', |_state| {
+                escape_roff(node_value(node, "content"))
+            })
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("[{}]", footnote.number),
+                None => escape_roff(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n.br\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_roff(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_roff(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_roff(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Outputs `ast` as a man(7) page. Takes its [`RoffOptions`] from `state`.
+pub fn to_roff(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let state = state.unwrap_or_default();
+    let options = state.options.get::<RoffOptions>();
+    let result = output_for(create_default_rules(), "roff")(ast, Some(state));
+
+    let mut page = String::new();
+    // Tells man to run the page through tbl, which lays out the tables
+    if result.contains("\n.TS\n") {
+        page.push_str("'\\\" t\n");
+    }
+    if !options.title.is_empty() {
+        let arguments: Vec<String> = [
+            &options.title,
+            &options.section,
+            &options.date,
+            &options.source,
+            &options.manual,
+        ]
+        .iter()
+        .map(|argument| quote_argument(argument))
+        .collect();
+        page.push_str(&format!(".TH {}\n", arguments.join(" ")));
+    }
+    page.push_str(&result);
+    page
+}
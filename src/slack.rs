@@ -0,0 +1,178 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_paragraphs, next_reference, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, task_marker, OutputFunction,
+    OutputState,
+};
+use crate::plain_text::PlainTextOptions;
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Escapes the control characters of Slack mrkdwn.
+pub fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn slack_link(target: &str, content: &str) -> String {
+    let target = escape_slack(target).replace('|', "%7C");
+    if content.is_empty() || content == target {
+        format!("<{}>", target)
+    } else {
+        format!("<{}|{}>", target, content)
+    }
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        // Slack has no headings, so they become a bold line
+        ("heading", |node, nested, state| {
+            format!(
+                "*{}*",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("hr", |_node, _nested, _state| "───\n\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            format!(
+                "```\n{}\n```\n\n",
+                escape_slack(node_value(node, "content"))
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            let quoted: Vec<String> = content
+                .trim_end()
+                .lines()
+                .map(|line| format!("> {}", line).trim_end().to_string())
+                .collect();
+            format!("{}\n\n", quoted.join("\n"))
+        }),
+        ("list", |node, nested, state| {
+            let ordered = node_value(node, "ordered") == "true";
+            let start: usize = node_value(node, "start").parse().unwrap_or(1);
+            // Nested lists are indented by a level for each list around them
+            let indent = "    ".repeat(state.lists.len());
+            let mut result = String::new();
+            let checked = list_checked(node);
+            for (i, item) in list_items(node).iter().enumerate() {
+                let bullet = if ordered {
+                    format!("{}.", start + i)
+                } else {
+                    "•".to_string()
+                };
+                let content = output_list_item(node, nested, item, state);
+                let task = task_marker(checked[i], "☐ ", "☑ ");
+                result.push_str(&format!(
+                    "{}{} {}{}\n",
+                    indent,
+                    bullet,
+                    task,
+                    content.trim()
+                ));
+            }
+            if state.lists.is_empty() {
+                result.push('\n');
+            }
+            result
+        }),
+        // Slack has no tables, so each row becomes a line with a bold header
+        ("table", |node, nested, state| {
+            let mut lines = Vec::new();
+            for (i, row) in output_table_rows(node, nested, state).iter().enumerate() {
+                let cells: Vec<String> = row
+                    .iter()
+                    .map(|cell| match cell.as_str() {
+                        "" => String::new(),
+                        cell if i == 0 => format!("*{}*", cell),
+                        cell => cell.to_string(),
+                    })
+                    .collect();
+                lines.push(cells.join(" | ").trim_end().to_string());
+            }
+            format!("{}\n\n", lines.join("\n"))
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_paragraphs(node, nested, state, |number| format!("[{}]", number))
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            slack_link(node_value(node, "target"), &content)
+        }),
+        // Images can't be inlined, so they link to the image instead
+        ("image", |node, _nested, _state| {
+            slack_link(
+                node_value(node, "target"),
+                &escape_slack(node_value(node, "alt")),
+            )
+        }),
+        ("em", |node, nested, state| {
+            format!(
+                "_{}_",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("strong", |node, nested, state| {
+            format!(
+                "*{}*",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        // Slack has no underline, so only the content is kept
+        ("u", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("del", |node, nested, state| {
+            format!(
+                "~{}~",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        // Slack has no spoilers, so they are masked as in plain text
+        ("spoiler", |_node, _nested, state| {
+            state.options.get::<PlainTextOptions>().spoiler_mask.clone()
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            format!("`{}`", escape_slack(node_value(node, "content")))
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("[{}]", footnote.number),
+                None => escape_slack(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_slack(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_slack(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_slack(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Converts `ast` to Slack mrkdwn.
+pub fn to_slack(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "slack")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use crate::default_rules::{create_default_rules, ASTNode, State};
+use crate::output::{node_value, OutputOptions, OutputState};
+use crate::parse::parser_for;
+use crate::plain_text::{to_plain_text, PlainTextOptions};
+
+/// Slugs `text` like GitHub does for heading anchors: lowercased, with
+/// punctuation removed and spaces replaced by hyphens.
+pub fn github_slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Hands out unique slugs, suffixing repeated ones with `-1`, `-2`, ... like GitHub.
+#[derive(Debug, Clone, Default)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = github_slug(text);
+        let mut slug = base.clone();
+        if let Some(&count) = self.occurrences.get(&base) {
+            let mut count = count;
+            loop {
+                count += 1;
+                slug = format!("{}-{}", base, count);
+                if !self.occurrences.contains_key(&slug) {
+                    break;
+                }
+            }
+            self.occurrences.insert(base, count);
+        }
+        self.occurrences.insert(slug.clone(), 0);
+        slug
+    }
+}
+
+/// Returns the slug of every heading in `ast`, in document order, e.g. to
+/// check `#fragment` links against. Headings with an `id` are slugged from it.
+pub fn heading_slugs(ast: &[ASTNode]) -> Vec<String> {
+    let parse = parser_for(create_default_rules());
+    let state = OutputState {
+        options: OutputOptions::default().with(PlainTextOptions {
+            link_urls: false,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut slugger = Slugger::default();
+    ast.iter()
+        .filter(|node| node_value(node, "type") == "heading")
+        .map(|node| match node.get("id") {
+            Some(id) => slugger.slug(id),
+            None => {
+                let content = parse(
+                    node_value(node, "content"),
+                    Some(State {
+                        inline: true,
+                        ..Default::default()
+                    }),
+                );
+                slugger.slug(&to_plain_text(&content, Some(state.clone())))
+            }
+        })
+        .collect()
+}
+
+/// Sets the `id` of every heading in `ast` to its slug, so the `html` output
+/// links to it.
+pub fn add_heading_ids(ast: &mut [ASTNode]) {
+    let slugs = heading_slugs(ast);
+    let headings = ast
+        .iter_mut()
+        .filter(|node| node_value(node, "type") == "heading");
+    for (node, slug) in headings.zip(slugs) {
+        node.insert("id".to_string(), slug);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::default_rules::{create_default_rules, list_item_content, ParserClosure, State};
+use crate::output::{list_checked, list_item_sources, list_items, node_value};
+use crate::parse::{parser_for, preprocess};
+
+/// Finds the task list markers of a document from its parsed lists.
+///
+/// Nested content, like the content of a list item or block quote, is parsed
+/// on its own, so each line of it comes with the offset where that line
+/// starts in the document. Every line of nested content is the end of a line
+/// of its parent, after the bullet, `>` or indentation stripped from it.
+struct MarkerFinder {
+    parse: ParserClosure,
+    /// Offsets in the preprocessed document of the `[ ]` and `[x]` markers,
+    /// pointing at the character between the brackets.
+    markers: Vec<usize>,
+}
+
+/// Returns the offset in the document of `offset` in `content`, given the
+/// offset in the document of each of its lines.
+fn document_offset(content: &str, starts: &[usize], offset: usize) -> usize {
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    starts[before.matches('\n').count()] + offset - line_start
+}
+
+/// Returns the offset in the document of each line of `nested`, which was
+/// stripped from `raw`, found at `raw_start` in `content`.
+fn nested_starts(
+    content: &str,
+    starts: &[usize],
+    raw_start: usize,
+    raw: &str,
+    nested: &str,
+) -> Vec<usize> {
+    let mut line_start = raw_start;
+    raw.split('\n')
+        .zip(nested.split('\n'))
+        .map(|(raw_line, line)| {
+            // Trailing spaces are stripped from the last line of list items
+            let raw_line_end = if raw_line.ends_with(line) {
+                raw_line.len()
+            } else {
+                raw_line.trim_end_matches(' ').len()
+            };
+            let start = document_offset(content, starts, line_start + raw_line_end - line.len());
+            line_start += raw_line.len() + 1;
+            start
+        })
+        .collect()
+}
+
+/// Returns the offset in the source before preprocessing of each byte of the
+/// preprocessed source, undoing what `preprocess` does to line endings, form
+/// feeds and tabs.
+fn source_offsets(source: &str) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\r' if chars.peek().is_some_and(|&(_, next)| next == '\n') => {}
+            '\r' => offsets.push(i),
+            '\u{c}' => {}
+            '\t' => offsets.extend([i; 4]),
+            _ => offsets.extend(i..i + c.len_utf8()),
+        }
+    }
+    offsets
+}
+
+impl MarkerFinder {
+    fn find(&mut self, content: &str, inline: bool, in_list: bool, starts: &[usize]) {
+        let state = State {
+            inline,
+            _list: in_list,
+            positions: true,
+            ..Default::default()
+        };
+        for node in (self.parse)(content, Some(state)) {
+            let Some(position) = node
+                .get("position")
+                .and_then(|position| serde_json::from_str::<serde_json::Value>(position).ok())
+            else {
+                continue;
+            };
+            let start = position["start"]["offset"].as_u64().unwrap_or(0) as usize;
+            let end = position["end"]["offset"].as_u64().unwrap_or(0) as usize;
+            match node_value(&node, "type") {
+                "list" => {
+                    let raw_items = list_item_sources(&node);
+                    // The list rule borrows the indentation of its first item
+                    // from what the previous rule consumed
+                    let indent = |text: &str| text.len() - text.trim_start_matches(' ').len();
+                    let borrowed = raw_items.first().map_or(0, |item| {
+                        indent(item).saturating_sub(indent(&content[start..]))
+                    });
+                    let mut item_start = start - borrowed;
+                    for ((raw, item), checked) in raw_items
+                        .iter()
+                        .zip(list_items(&node))
+                        .zip(list_checked(&node))
+                    {
+                        let item_starts = nested_starts(content, starts, item_start, raw, &item);
+                        if checked.is_some() {
+                            let first_line =
+                                |text: &str| text.split('\n').next().unwrap_or("").len();
+                            let marker = first_line(raw) - first_line(&list_item_content(raw)) + 1;
+                            self.markers.push(document_offset(
+                                content,
+                                starts,
+                                item_start + marker,
+                            ));
+                        }
+                        // Items are parsed like `output_list_item` does
+                        if item.contains("\n\n") {
+                            self.find(&format!("{}\n\n", item), false, true, &item_starts);
+                        } else {
+                            self.find(&item, true, true, &item_starts);
+                        }
+                        item_start += raw.len();
+                    }
+                }
+                "blockQuote" => {
+                    let quoted = node_value(&node, "content");
+                    let quote_starts =
+                        nested_starts(content, starts, start, &content[start..end], quoted);
+                    self.find(quoted, false, in_list, &quote_starts);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Toggles the checked state of a task list item and returns `source` with
+/// only that item's `[ ]` or `[x]` marker changed.
+///
+/// `index` counts the task list items of the document from 0, in the order
+/// they are output, including those of nested lists and block quotes. Returns
+/// `None` if the document has fewer task list items.
+pub fn toggle_task_item(source: &str, index: usize) -> Option<String> {
+    let content = preprocess(source);
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut finder = MarkerFinder {
+        parse: parser_for(create_default_rules()),
+        markers: Vec::new(),
+    };
+    finder.find(&content, false, false, &starts);
+
+    let marker = source_offsets(source)[*finder.markers.get(index)?];
+    let toggled = if &source[marker..marker + 1] == " " {
+        "x"
+    } else {
+        " "
+    };
+    let mut result = source.to_string();
+    result.replace_range(marker..marker + 1, toggled);
+    Some(result)
+}
@@ -0,0 +1,365 @@
+use fancy_regex::Regex;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_paragraphs, next_reference, reference_source};
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, output_block, output_for, output_inline,
+    output_link_content, output_list_item, output_table_rows, task_marker, NestedOutput,
+    OutputFunction, OutputState,
+};
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Escapes every character MarkdownV2 reserves outside of entities.
+pub fn escape_markdown_v2(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Escapes text inside `code` and `pre` entities, where only `` ` `` and `\` are reserved.
+fn escape_code(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+/// Escapes the url of an inline link, where only `)` and `\` are reserved.
+fn escape_link_url(url: &str) -> String {
+    url.replace('\\', "\\\\").replace(')', "\\)")
+}
+
+/// Escapes text for Telegram's HTML parse mode.
+pub fn escape_telegram_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn quote_lines(content: &str, prefix: &str) -> String {
+    content
+        .trim_end()
+        .lines()
+        .map(|line| format!("{}{}", prefix, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn list_lines(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    state: &mut OutputState,
+    escape: fn(&str) -> String,
+) -> String {
+    let ordered = node_value(node, "ordered") == "true";
+    let start: usize = node_value(node, "start").parse().unwrap_or(1);
+    // Nested lists are indented by a level for each list around them
+    let indent = "    ".repeat(state.lists.len());
+    let mut result = String::new();
+    let checked = list_checked(node);
+    for (i, item) in list_items(node).iter().enumerate() {
+        let bullet = if ordered {
+            escape(&format!("{}.", start + i))
+        } else {
+            "•".to_string()
+        };
+        let content = output_list_item(node, nested, item, state);
+        let task = task_marker(checked[i], "☐ ", "☑ ");
+        result.push_str(&format!(
+            "{}{} {}{}\n",
+            indent,
+            bullet,
+            task,
+            content.trim()
+        ));
+    }
+    if state.lists.is_empty() {
+        result.push('\n');
+    }
+    result
+}
+
+/// Outputs a table as a line per row, as Telegram has no tables. Header cells
+/// are wrapped in `bold` and cells are separated by `separator`.
+fn table_lines(
+    node: &ASTNode,
+    nested: &NestedOutput,
+    state: &mut OutputState,
+    bold: fn(&str) -> String,
+    separator: &str,
+) -> String {
+    let mut lines = Vec::new();
+    for (i, row) in output_table_rows(node, nested, state).iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|cell| {
+                if i == 0 && !cell.is_empty() {
+                    bold(cell)
+                } else {
+                    cell.clone()
+                }
+            })
+            .collect();
+        lines.push(cells.join(separator).trim_end().to_string());
+    }
+    format!("{}\n\n", lines.join("\n"))
+}
+
+/// Output functions for the `telegram` format, Telegram's MarkdownV2.
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        // Telegram has no headings, so they become a bold line
+        ("heading", |node, nested, state| {
+            format!(
+                "*{}*",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("hr", |_node, _nested, _state| "———\n\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            // Languages can't be escaped, so ones that could end the fence are dropped
+            let lang = node_value(node, "lang");
+            let lang = if lang
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c))
+            {
+                lang
+            } else {
+                ""
+            };
+            format!(
+                "```{}\n{}\n```\n\n",
+                lang,
+                escape_code(node_value(node, "content"))
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            format!("{}\n\n", quote_lines(&content, ">"))
+        }),
+        ("list", |node, nested, state| {
+            list_lines(node, nested, state, escape_markdown_v2)
+        }),
+        ("table", |node, nested, state| {
+            table_lines(node, nested, state, |cell| format!("*{}*", cell), " \\| ")
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_paragraphs(node, nested, state, |number| format!("\\[{}\\]", number))
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            format!(
+                "[{}]({})",
+                content,
+                escape_link_url(node_value(node, "target"))
+            )
+        }),
+        // Images can't be inlined, so they link to the image instead
+        ("image", |node, _nested, _state| {
+            format!(
+                "[{}]({})",
+                escape_markdown_v2(node_value(node, "alt")),
+                escape_link_url(node_value(node, "target"))
+            )
+        }),
+        ("em", |node, nested, state| {
+            format!(
+                "_{}_",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("strong", |node, nested, state| {
+            format!(
+                "*{}*",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("u", |node, nested, state| {
+            format!(
+                "__{}__",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("del", |node, nested, state| {
+            format!(
+                "~{}~",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("spoiler", |node, nested, state| {
+            format!(
+                "||{}||",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            format!("`{}`", escape_code(node_value(node, "content")))
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("\\[{}\\]", footnote.number),
+                None => escape_markdown_v2(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_markdown_v2(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_markdown_v2(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_markdown_v2(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Output functions for the `telegramHtml` format, Telegram's HTML subset.
+pub(crate) fn html_outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            format!(
+                "<b>{}</b>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("hr", |_node, _nested, _state| "———\n\n".to_string()),
+        ("codeBlock", |node, _nested, _state| {
+            let content = escape_telegram_html(node_value(node, "content"));
+            let lang = node_value(node, "lang");
+            if lang.is_empty() {
+                format!("<pre>{}</pre>\n\n", content)
+            } else {
+                format!(
+                    "<pre><code class=\"language-{}\">{}</code></pre>\n\n",
+                    escape_telegram_html(lang),
+                    content
+                )
+            }
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = output_block(nested, node_value(node, "content"), state);
+            format!("<blockquote>{}</blockquote>\n\n", content.trim())
+        }),
+        ("list", |node, nested, state| {
+            list_lines(node, nested, state, escape_telegram_html)
+        }),
+        ("table", |node, nested, state| {
+            table_lines(
+                node,
+                nested,
+                state,
+                |cell| format!("<b>{}</b>", cell),
+                " | ",
+            )
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            footnote_paragraphs(node, nested, state, |number| format!("[{}]", number))
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            output_inline(nested, node_value(node, "content"), state)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            format!(
+                "<a href=\"{}\">{}</a>",
+                escape_telegram_html(node_value(node, "target")),
+                content
+            )
+        }),
+        ("image", |node, _nested, _state| {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                escape_telegram_html(node_value(node, "target")),
+                escape_telegram_html(node_value(node, "alt"))
+            )
+        }),
+        ("em", |node, nested, state| {
+            format!(
+                "<i>{}</i>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("strong", |node, nested, state| {
+            format!(
+                "<b>{}</b>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("u", |node, nested, state| {
+            format!(
+                "<u>{}</u>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("del", |node, nested, state| {
+            format!(
+                "<s>{}</s>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("spoiler", |node, nested, state| {
+            format!(
+                "<tg-spoiler>{}</tg-spoiler>",
+                output_inline(nested, node_value(node, "content"), state)
+            )
+        }),
+        ("inlineCode", |node, _nested, _state| {
+            format!(
+                "<code>{}</code>",
+                escape_telegram_html(node_value(node, "content"))
+            )
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => format!("[{}]", footnote.number),
+                None => escape_telegram_html(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n".to_string()),
+        ("mention", |node, _nested, state| {
+            escape_telegram_html(&mention_text(node, state.mentions.as_deref()))
+        }),
+        ("emoji", |node, _nested, _state| {
+            escape_telegram_html(&emoji_text(node))
+        }),
+        ("text", |node, _nested, _state| {
+            escape_telegram_html(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Converts `ast` to Telegram MarkdownV2, for `parse_mode: "MarkdownV2"`.
+pub fn to_telegram_markdown(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "telegram")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
+
+/// Converts `ast` to Telegram's HTML subset, for `parse_mode: "HTML"`.
+pub fn to_telegram_html(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "telegramHtml")(ast, state);
+    EXTRA_NEWLINES_R
+        .replace_all(&result, "\n\n")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,375 @@
+use fancy_regex::Regex;
+use std::rc::Rc;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::emoji_text;
+use crate::footnotes::{footnote_items, next_reference, output_footnote, reference_source};
+use crate::highlight::TokenKind;
+use crate::mention::mention_text;
+use crate::output::{
+    align_cell, column_widths, list_checked, list_items, node_value, output_block, output_for,
+    output_inline, output_link_content, output_list_item, output_table_rows, table_align,
+    task_marker, OutputFunction, OutputState,
+};
+use crate::plain_text::PlainTextOptions;
+
+lazy_static::lazy_static! {
+    static ref ESCAPE_SEQUENCE_R: Regex = Regex::new(r"\x1b\[[0-9;]*m|\x1b\]8;;[^\x1b]*\x1b\\").unwrap();
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+const MIN_WIDTH: usize = 20;
+
+/// Options for `to_terminal`.
+#[derive(Debug, Clone)]
+pub struct TerminalOptions {
+    /// Column at which paragraphs, headings and list items are wrapped.
+    pub width: usize,
+    /// Style text with ANSI escape codes. When false, no escape codes are emitted.
+    pub color: bool,
+    /// Output links as OSC 8 hyperlinks instead of `text (url)`. Needs `color`.
+    pub hyperlinks: bool,
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        TerminalOptions {
+            width: 80,
+            color: true,
+            hyperlinks: true,
+        }
+    }
+}
+
+/// Returns the number of columns `text` takes up, ignoring escape sequences.
+pub(crate) fn visible_width(text: &str) -> usize {
+    ESCAPE_SEQUENCE_R.replace_all(text, "").chars().count()
+}
+
+/// Removes control characters other than newlines and tabs, so the document
+/// can't move the cursor or start escape sequences of its own.
+fn strip_controls(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !c.is_control() || c == '\n' || c == '\t')
+        .collect()
+}
+
+fn options(state: &OutputState) -> Rc<TerminalOptions> {
+    state.options.get()
+}
+
+fn style(state: &OutputState, text: &str, open: u8, close: u8) -> String {
+    if options(state).color {
+        format!("\x1b[{}m{}\x1b[{}m", open, text, close)
+    } else {
+        text.to_string()
+    }
+}
+
+fn wrap(text: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut current = String::new();
+        let mut current_width = 0;
+        for word in line.split(' ') {
+            let word_width = visible_width(word);
+            if current_width > 0 && current_width + 1 + word_width > width {
+                lines.push(current);
+                current = String::new();
+                current_width = 0;
+            } else if !current.is_empty() {
+                current.push(' ');
+                current_width += 1;
+            }
+            current.push_str(word);
+            current_width += word_width;
+        }
+        lines.push(current);
+    }
+    lines.join("\n")
+}
+
+fn indent(text: &str, first: &str, rest: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let prefix = if i == 0 { first } else { rest };
+            format!("{}{}", prefix, line).trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs `output` with `columns` fewer columns to wrap nested content in.
+fn with_indent(
+    state: &mut OutputState,
+    columns: usize,
+    output: impl FnOnce(&mut OutputState) -> String,
+) -> String {
+    let options = options(state);
+    state.options.set(TerminalOptions {
+        width: options.width.saturating_sub(columns).max(MIN_WIDTH),
+        ..(*options).clone()
+    });
+    let result = output(state);
+    state.options.set((*options).clone());
+    result
+}
+
+fn token_color(kind: TokenKind) -> u8 {
+    match kind {
+        TokenKind::Keyword => 35,
+        TokenKind::Literal | TokenKind::Number => 36,
+        TokenKind::String => 32,
+        TokenKind::Comment => 90,
+    }
+}
+
+/// Colors the content of a `codeBlock` node if there is a highlighter and a
+/// language. Spans are styled line by line, so every line of the box is closed.
+fn highlighted_code(node: &ASTNode, state: &OutputState) -> String {
+    let content = strip_controls(node_value(node, "content"));
+    let lang = node_value(node, "lang");
+    match &state.highlighter {
+        Some(highlighter) if !lang.is_empty() && options(state).color => highlighter
+            .highlight(lang, &content)
+            .iter()
+            .map(|span| match span.kind {
+                Some(kind) => span
+                    .text
+                    .split('\n')
+                    .map(|line| style(state, line, token_color(kind), 39))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                None => span.text.clone(),
+            })
+            .collect(),
+        _ => content,
+    }
+}
+
+fn code_box(content: &str, lang: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let label = if lang.is_empty() {
+        String::new()
+    } else {
+        format!("─ {} ", lang)
+    };
+    let inner_width = lines
+        .iter()
+        .map(|line| visible_width(line) + 2)
+        .max()
+        .unwrap_or(2)
+        .max(label.chars().count() + 1);
+
+    let mut result = format!(
+        "┌{}{}┐\n",
+        label,
+        "─".repeat(inner_width - label.chars().count())
+    );
+    for line in lines {
+        let padding = inner_width - 1 - visible_width(line);
+        result.push_str(&format!("│ {}{}│\n", line, " ".repeat(padding)));
+    }
+    result.push_str(&format!("└{}┘\n\n", "─".repeat(inner_width)));
+    result
+}
+
+/// Draws a box around table cells, with a rule under the header row.
+fn table_box(rows: &[Vec<String>], align: &[String]) -> String {
+    let widths = column_widths(rows, visible_width);
+    let rule = |left: &str, middle: &str, right: &str| {
+        let lines: Vec<String> = widths.iter().map(|width| "─".repeat(width + 2)).collect();
+        format!("{}{}{}\n", left, lines.join(middle), right)
+    };
+    let mut result = rule("┌", "┬", "┐");
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| {
+                let align = align.get(column).map_or("", String::as_str);
+                align_cell(cell, visible_width(cell), widths[column], align)
+            })
+            .collect();
+        result.push_str(&format!("│ {} │\n", cells.join(" │ ")));
+        if i == 0 {
+            result.push_str(&rule("├", "┼", "┤"));
+        }
+    }
+    result.push_str(&rule("└", "┴", "┘"));
+    result.push('\n');
+    result
+}
+
+pub(crate) fn outputs() -> Vec<(&'static str, OutputFunction)> {
+    vec![
+        ("heading", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            let content = if node_value(node, "level") == "1" {
+                style(state, &content, 4, 24)
+            } else {
+                content
+            };
+            wrap(&style(state, &content, 1, 22), options(state).width)
+        }),
+        ("hr", |_node, _nested, state| {
+            format!("{}\n\n", "─".repeat(options(state).width))
+        }),
+        ("codeBlock", |node, _nested, state| {
+            code_box(
+                &highlighted_code(node, state),
+                &strip_controls(node_value(node, "lang")),
+            )
+        }),
+        ("blockQuote", |node, nested, state| {
+            let content = with_indent(state, 2, |state| {
+                output_block(nested, node_value(node, "content"), state)
+            });
+            let bar = style(state, "│", 2, 22);
+            let prefix = format!("{} ", bar);
+            format!("{}\n\n", indent(content.trim_end(), &prefix, &prefix))
+        }),
+        ("list", |node, nested, state| {
+            let ordered = node_value(node, "ordered") == "true";
+            let start: usize = node_value(node, "start").parse().unwrap_or(1);
+            let mut result = String::new();
+            let checked = list_checked(node);
+            for (i, item) in list_items(node).iter().enumerate() {
+                let bullet = if ordered {
+                    format!("{}. ", start + i)
+                } else {
+                    "• ".to_string()
+                };
+                let bullet = format!("{}{}", bullet, task_marker(checked[i], "☐ ", "☑ "));
+                let columns = bullet.chars().count();
+                let content = with_indent(state, columns, |state| {
+                    let content = output_list_item(node, nested, item, state);
+                    wrap(content.trim_end(), options(state).width)
+                });
+                result.push_str(&indent(&content, &bullet, &" ".repeat(columns)));
+                result.push('\n');
+            }
+            result.push('\n');
+            result
+        }),
+        ("table", |node, nested, state| {
+            let mut rows = output_table_rows(node, nested, state);
+            if let Some(header) = rows.first_mut() {
+                for cell in header.iter_mut() {
+                    *cell = style(state, cell, 1, 22);
+                }
+            }
+            table_box(&rows, &table_align(node))
+        }),
+        ("def", |_node, _nested, _state| String::new()),
+        ("footnoteDef", |_node, _nested, _state| String::new()),
+        ("footnotes", |node, nested, state| {
+            let mut result = format!("\n\n{}\n", style(state, &"─".repeat(MIN_WIDTH), 2, 22));
+            for footnote in footnote_items(node) {
+                let marker = format!("[{}] ", footnote.number);
+                let columns = marker.chars().count();
+                let content = with_indent(state, columns, |state| {
+                    output_footnote(nested, &footnote, state)
+                });
+                result.push_str(&indent(content.trim_end(), &marker, &" ".repeat(columns)));
+                result.push_str("\n\n");
+            }
+            result
+        }),
+        ("newline", |_node, _nested, _state| "\n".to_string()),
+        ("paragraph", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            wrap(&content, options(state).width)
+        }),
+        ("link", |node, nested, state| {
+            let content = output_link_content(node, nested, state);
+            let target = strip_controls(node_value(node, "target"));
+            let options = options(state);
+            if options.color && options.hyperlinks {
+                format!(
+                    "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\",
+                    target,
+                    style(state, &content, 4, 24)
+                )
+            } else if target.is_empty()
+                || target == content
+                || target.strip_prefix("mailto:") == Some(content.as_str())
+            {
+                content
+            } else {
+                format!("{} ({})", content, target)
+            }
+        }),
+        ("image", |node, _nested, state| {
+            let content = format!("[image: {}]", strip_controls(node_value(node, "alt")));
+            let target = strip_controls(node_value(node, "target"));
+            let options = options(state);
+            if options.color && options.hyperlinks {
+                format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", target, content)
+            } else {
+                format!("{} ({})", content, target)
+            }
+        }),
+        ("em", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            style(state, &content, 3, 23)
+        }),
+        ("strong", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            style(state, &content, 1, 22)
+        }),
+        ("u", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            style(state, &content, 4, 24)
+        }),
+        ("del", |node, nested, state| {
+            let content = output_inline(nested, node_value(node, "content"), state);
+            style(state, &content, 9, 29)
+        }),
+        // Terminals can't reveal text on click, so spoilers are masked as in plain text
+        ("spoiler", |_node, _nested, state| {
+            style(
+                state,
+                &state.options.get::<PlainTextOptions>().spoiler_mask,
+                2,
+                22,
+            )
+        }),
+        ("inlineCode", |node, _nested, state| {
+            let content = strip_controls(node_value(node, "content"));
+            if options(state).color {
+                style(state, &content, 36, 39)
+            } else {
+                format!("`{}`", content)
+            }
+        }),
+        ("footnoteRef", |node, _nested, state| {
+            match next_reference(node, state) {
+                Some((footnote, _)) => style(state, &format!("[{}]", footnote.number), 2, 22),
+                None => strip_controls(&reference_source(node)),
+            }
+        }),
+        ("br", |_node, _nested, _state| "\n".to_string()),
+        ("mention", |node, _nested, state| {
+            let text = strip_controls(&mention_text(node, state.mentions.as_deref()));
+            style(state, &text, 1, 22)
+        }),
+        ("emoji", |node, _nested, _state| emoji_text(node)),
+        ("text", |node, _nested, _state| {
+            strip_controls(node_value(node, "content"))
+        }),
+    ]
+}
+
+/// Outputs `ast` as text styled with ANSI escape codes, for printing to a terminal.
+///
+/// Takes its [`TerminalOptions`] from `state`. Code blocks with a language are
+/// colored by the state's highlighter, if any.
+pub fn to_terminal(ast: &[ASTNode], state: Option<OutputState>) -> String {
+    let result = output_for(create_default_rules(), "terminal")(ast, state);
+    format!(
+        "{}\n",
+        EXTRA_NEWLINES_R.replace_all(&result, "\n\n").trim_end()
+    )
+}
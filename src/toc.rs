@@ -0,0 +1,151 @@
+use crate::default_rules::{create_default_rules, ASTNode, State};
+use crate::output::{
+    node_value, output_for, output_link_content, register_outputs, set_list_items, OutputState,
+};
+use crate::parse::parser_for;
+use crate::slug::heading_slugs;
+
+/// Options for `table_of_contents`.
+#[derive(Debug, Clone)]
+pub struct TocOptions {
+    /// Shallowest heading level included, e.g. 2 to leave out the title.
+    pub min_depth: usize,
+    /// Deepest heading level included.
+    pub max_depth: usize,
+}
+
+impl Default for TocOptions {
+    fn default() -> Self {
+        TocOptions {
+            min_depth: 1,
+            max_depth: 6,
+        }
+    }
+}
+
+/// A heading in a table of contents, with the headings under it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub level: usize,
+    /// Content of the heading, as markdown source.
+    pub content: String,
+    /// Slug of the heading, as given by `heading_slugs`.
+    pub slug: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Collects the headings of `ast` into a nested outline. A heading is nested
+/// under the closest preceding heading with a lower level.
+pub fn table_of_contents(ast: &[ASTNode], options: &TocOptions) -> Vec<TocEntry> {
+    let mut stack: Vec<TocEntry> = Vec::new();
+    let mut result = Vec::new();
+
+    let close_until = |stack: &mut Vec<TocEntry>, result: &mut Vec<TocEntry>, level: usize| {
+        while stack.last().is_some_and(|entry| entry.level >= level) {
+            let entry = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(entry),
+                None => result.push(entry),
+            }
+        }
+    };
+
+    // Slugs are counted over every heading, so they match the ids of the document
+    let headings = ast
+        .iter()
+        .filter(|node| node_value(node, "type") == "heading");
+    for (node, slug) in headings.zip(heading_slugs(ast)) {
+        let level: usize = node_value(node, "level").parse().unwrap_or(1);
+        if level < options.min_depth || level > options.max_depth {
+            continue;
+        }
+        close_until(&mut stack, &mut result, level);
+        stack.push(TocEntry {
+            level,
+            content: node_value(node, "content").to_string(),
+            slug,
+            children: Vec::new(),
+        });
+    }
+    close_until(&mut stack, &mut result, 0);
+    result
+}
+
+fn list_source(entries: &[TocEntry], ordered: bool, unlink: &dyn Fn(&str) -> String) -> String {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let bullet = if ordered {
+                format!("{}.", i + 1)
+            } else {
+                "*".to_string()
+            };
+            let item = item_source(entry, ordered, unlink).replace('\n', "\n    ");
+            format!("{} {}", bullet, item)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Source of a list item linking to the heading, with the children as a
+/// nested list. Like items parsed by the `list` rule, the nested list isn't
+/// indented.
+fn item_source(entry: &TocEntry, ordered: bool, unlink: &dyn Fn(&str) -> String) -> String {
+    let link = format!("[{}](#{})", unlink(&entry.content), entry.slug);
+    if entry.children.is_empty() {
+        return link;
+    }
+    format!(
+        "{}\n{}",
+        link,
+        list_source(&entry.children, ordered, unlink)
+    )
+}
+
+/// Returns a function replacing the links in the content of a heading with
+/// their content, since links can't be nested in the link to the heading.
+/// Footnote references are links too, and are left out.
+fn unlink_content() -> impl Fn(&str) -> String {
+    let mut rules = create_default_rules();
+    register_outputs(
+        &mut rules,
+        "markdown",
+        vec![
+            ("link", |node, nested, state| {
+                output_link_content(node, nested, state)
+            }),
+            ("footnoteRef", |_node, _nested, _state| String::new()),
+        ],
+    );
+    let parse = parser_for(rules.clone());
+    let output = output_for(rules, "markdown");
+    move |content| {
+        let state = State {
+            inline: true,
+            ..Default::default()
+        };
+        let output_state = OutputState {
+            inline: true,
+            ..Default::default()
+        };
+        output(&parse(content, Some(state)), Some(output_state))
+    }
+}
+
+/// Renders a table of contents as a `list` node of links to the headings, to
+/// insert into a document.
+pub fn toc_to_list(entries: &[TocEntry], ordered: bool) -> ASTNode {
+    let unlink = unlink_content();
+    let items: Vec<String> = entries
+        .iter()
+        .map(|entry| item_source(entry, ordered, &unlink))
+        .collect();
+    let mut list = ASTNode::from([
+        ("type".to_string(), "list".to_string()),
+        ("ordered".to_string(), ordered.to_string()),
+        ("start".to_string(), String::new()),
+    ]);
+    set_list_items(&mut list, &items);
+    list
+}
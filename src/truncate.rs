@@ -0,0 +1,285 @@
+use fancy_regex::Regex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::footnotes::footnote_items;
+use crate::markdown::escape_markdown;
+use crate::output::{
+    list_items, node_value, output_for, set_list_items, OutputClosure, OutputOptions, OutputState,
+};
+use crate::parse::NestedParser;
+use crate::plain_text::PlainTextOptions;
+
+lazy_static::lazy_static! {
+    static ref EXTRA_NEWLINES_R: Regex = Regex::new(r"\n{3,}").unwrap();
+}
+
+/// Finishes plain text or markdown output the way `to_plain_text` and
+/// `to_markdown` do.
+fn finish(output: &str) -> String {
+    EXTRA_NEWLINES_R
+        .replace_all(output, "\n\n")
+        .trim()
+        .to_string()
+}
+
+/// How `truncate` measures the length of an AST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncateCount {
+    /// Characters of the plain text a reader sees, as given by `to_plain_text`
+    /// without link urls.
+    #[default]
+    Visible,
+    /// Characters of the markdown source, as given by `to_markdown`.
+    Markdown,
+}
+
+struct Truncator<'a> {
+    nested: NestedParser,
+    /// Output of the format measured, as given by `count`.
+    output: OutputClosure,
+    markdown: OutputClosure,
+    state: OutputState,
+    /// Output of each node rendered so far, keyed by its sorted fields.
+    rendered: RefCell<HashMap<String, String>>,
+    ellipsis: &'a str,
+    count: TruncateCount,
+}
+
+impl Truncator<'_> {
+    /// Outputs `ast` in the format measured. Nodes are output one at a time
+    /// with the same state, so their outputs can be concatenated and each
+    /// node is only output once, however many candidates it is measured in.
+    fn render(&self, ast: &[ASTNode]) -> String {
+        let mut result = String::new();
+        for node in ast {
+            let key = format!("{:?}", node.iter().collect::<BTreeMap<_, _>>());
+            if let Some(rendered) = self.rendered.borrow().get(&key) {
+                result.push_str(rendered);
+                continue;
+            }
+            let rendered = (self.output)(std::slice::from_ref(node), Some(self.state.clone()));
+            result.push_str(&rendered);
+            self.rendered.borrow_mut().insert(key, rendered);
+        }
+        result
+    }
+
+    /// Length of the output `rendered`, once finished like `to_plain_text`
+    /// and `to_markdown` finish theirs.
+    fn rendered_len(&self, rendered: &str) -> usize {
+        finish(rendered).chars().count()
+    }
+
+    fn measure(&self, ast: &[ASTNode]) -> usize {
+        self.rendered_len(&self.render(ast))
+    }
+
+    /// Writes `ast` back to markdown, like `to_markdown`.
+    fn source(&self, ast: &[ASTNode]) -> String {
+        finish(&(self.markdown)(ast, Some(OutputState::default())))
+    }
+
+    fn text_len(&self, text: &str) -> usize {
+        match self.count {
+            TruncateCount::Visible => text.chars().count(),
+            TruncateCount::Markdown => escape_markdown(text).chars().count(),
+        }
+    }
+
+    /// Node holding only the ellipsis, for when nothing can be cut to make room.
+    fn ellipsis_node(&self, inline: bool) -> ASTNode {
+        if inline {
+            ASTNode::from([
+                ("type".to_string(), "text".to_string()),
+                ("content".to_string(), self.ellipsis.to_string()),
+            ])
+        } else {
+            ASTNode::from([
+                ("type".to_string(), "paragraph".to_string()),
+                ("content".to_string(), escape_markdown(self.ellipsis)),
+            ])
+        }
+    }
+
+    /// Keeps the longest run of whole nodes that fits in `max` along with the
+    /// ellipsis, then cuts into the next node to fill the rest. The result
+    /// always ends with the ellipsis, even if all of `ast` would fit.
+    fn nodes(&self, ast: &[ASTNode], max: usize, inline: bool) -> Vec<ASTNode> {
+        let ellipsis_len = self.text_len(self.ellipsis);
+        let mut kept = 0;
+        let mut rendered = String::new();
+        let mut prefix_len = 0;
+        while kept < ast.len() {
+            rendered.push_str(&self.render(&ast[kept..=kept]));
+            let len = self.rendered_len(&rendered);
+            if len + ellipsis_len > max {
+                break;
+            }
+            prefix_len = len;
+            kept += 1;
+        }
+        let prefix = &ast[..kept];
+
+        // Measuring the result as a whole accounts for separators between
+        // blocks, so the budget of the cut node shrinks until it fits
+        let mut budget = max.saturating_sub(prefix_len);
+        while budget >= ellipsis_len && kept < ast.len() {
+            let Some(cut) = self.node(&ast[kept], budget) else {
+                break;
+            };
+            let mut result = prefix.to_vec();
+            result.push(cut);
+            let len = self.measure(&result);
+            if len <= max {
+                return result;
+            }
+            budget -= (len - max).min(budget);
+        }
+
+        let mut result = prefix.to_vec();
+        if let Some(last) = result
+            .last_mut()
+            .filter(|node| node_value(node, "type") == "text")
+        {
+            let content = node_value(last, "content").trim_end().to_string();
+            last.insert("content".to_string(), content);
+        }
+        result.push(self.ellipsis_node(inline));
+        if self.measure(&result) > max {
+            result.pop();
+        }
+        result
+    }
+
+    /// Cuts the content of `node` so that it measures at most `max`, ending it
+    /// with the ellipsis. Returns `None` for nodes that can't be cut.
+    fn node(&self, node: &ASTNode, max: usize) -> Option<ASTNode> {
+        let content = node_value(node, "content");
+        let mut result = node.clone();
+        match node_value(node, "type") {
+            "text" | "inlineCode" | "codeBlock" => {
+                let overhead = self
+                    .measure(std::slice::from_ref(node))
+                    .saturating_sub(self.text_len(content));
+                let budget = max.checked_sub(overhead + self.text_len(self.ellipsis))?;
+                let mut cut = String::new();
+                let mut len = 0;
+                for c in content.chars() {
+                    len += self.text_len(c.encode_utf8(&mut [0; 4]));
+                    if len > budget {
+                        break;
+                    }
+                    cut.push(c);
+                }
+                result.insert(
+                    "content".to_string(),
+                    format!("{}{}", cut.trim_end(), self.ellipsis),
+                );
+            }
+            "heading" | "paragraph" | "em" | "strong" | "u" | "del" | "spoiler" | "link" => {
+                let in_link = node_value(node, "type") == "link";
+                let children = self.nested.parse(content, true, true, in_link);
+                let overhead = self
+                    .measure(std::slice::from_ref(node))
+                    .saturating_sub(self.measure(&children));
+                let children = self.nodes(&children, max.checked_sub(overhead)?, true);
+                result.insert("content".to_string(), self.source(&children));
+            }
+            "blockQuote" => {
+                let children = self.nested.parse(content, false, true, false);
+                let overhead = self
+                    .measure(std::slice::from_ref(node))
+                    .saturating_sub(self.measure(&children));
+                let children = self.nodes(&children, max.checked_sub(overhead)?, false);
+                result.insert(
+                    "content".to_string(),
+                    format!("{}\n\n", self.source(&children)),
+                );
+            }
+            "list" => {
+                let items = list_items(node);
+                let with_items = |items: &[String]| {
+                    let mut list = node.clone();
+                    set_list_items(&mut list, items);
+                    list
+                };
+                let mut kept = 0;
+                while kept < items.len()
+                    && self.measure(&[with_items(&items[..=kept])]) + self.text_len(self.ellipsis)
+                        <= max
+                {
+                    kept += 1;
+                }
+                let item = items.get(kept)?;
+                let block = item.contains("\n\n");
+                let children = if block {
+                    self.nested
+                        .parse(&format!("{}\n\n", item), false, true, false)
+                } else {
+                    self.nested.parse(item, true, true, false)
+                };
+                let mut cut_items = items[..kept].to_vec();
+                // The new item counts as much as an empty one in the list
+                cut_items.push(String::new());
+                let overhead = self.measure(&[with_items(&cut_items)]);
+                let children = self.nodes(&children, max.checked_sub(overhead)?, !block);
+                cut_items.pop();
+                cut_items.push(self.source(&children));
+                result = with_items(&cut_items);
+            }
+            _ => return None,
+        }
+        Some(result)
+    }
+}
+
+/// Shortens `ast` so it measures at most `max_chars`, ending with `ellipsis`
+/// where it was cut, e.g. to fit a preview or an embed with a length limit.
+///
+/// Nodes are cut through their content and serialized back, so the result
+/// never has unclosed formatting, fences or links. Nodes that can't be cut,
+/// like images, are left out whole. An AST that already fits is returned as is.
+pub fn truncate(
+    ast: &[ASTNode],
+    max_chars: usize,
+    ellipsis: &str,
+    count: TruncateCount,
+) -> Vec<ASTNode> {
+    let rules = create_default_rules();
+    let format = match count {
+        TruncateCount::Visible => "text",
+        TruncateCount::Markdown => "markdown",
+    };
+    let mut state = OutputState {
+        options: OutputOptions::default().with(PlainTextOptions {
+            link_urls: false,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    // Nodes are rendered on their own, so references need the footnotes up front
+    if let Some(section) = ast
+        .iter()
+        .find(|node| node_value(node, "type") == "footnotes")
+    {
+        state.footnotes = footnote_items(section)
+            .into_iter()
+            .map(|footnote| (footnote.label.clone(), footnote))
+            .collect();
+    }
+    let truncator = Truncator {
+        nested: NestedParser::new(rules.clone()),
+        output: output_for(rules.clone(), format),
+        markdown: output_for(rules, "markdown"),
+        state,
+        rendered: RefCell::new(HashMap::new()),
+        ellipsis,
+        count,
+    };
+    if truncator.measure(ast) <= max_chars {
+        return ast.to_vec();
+    }
+    truncator.nodes(ast, max_chars, false)
+}
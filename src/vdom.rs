@@ -0,0 +1,341 @@
+use serde_json::{json, Map, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::default_rules::{create_default_rules, ASTNode};
+use crate::emoji::{emoji_text, emoji_url, EmojiOptions};
+use crate::footnotes::{footnote_id, footnote_items, reference_id, reference_source, Footnote};
+use crate::html::sanitize_url;
+use crate::mention::mention_text;
+use crate::output::{
+    list_checked, list_items, node_value, table_align, table_cells, table_header, OutputState,
+};
+use crate::parse::NestedParser;
+
+/// Builds a `{tag, props, children, key}` element, leaving out empty props.
+fn element(tag: &str, props: &[(&str, &str)], children: Vec<Value>, key: &str) -> Value {
+    let props: Map<String, Value> = props
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect();
+    json!({ "tag": tag, "props": props, "children": children, "key": key })
+}
+
+struct VdomWriter {
+    nested: NestedParser,
+    state: OutputState,
+    footnotes: HashMap<String, Footnote>,
+    /// Number of references converted so far to each footnote, by label.
+    references: RefCell<HashMap<String, usize>>,
+    /// Set while converting link content.
+    in_link: Cell<bool>,
+}
+
+impl VdomWriter {
+    /// Converts `ast` to children, keying each element by its index path from
+    /// the root, e.g. `"2.0.1"`.
+    fn children(&self, ast: &[ASTNode], parent_key: &str) -> Vec<Value> {
+        let mut children: Vec<Value> = Vec::new();
+        for node in ast {
+            let key = if parent_key.is_empty() {
+                children.len().to_string()
+            } else {
+                format!("{}.{}", parent_key, children.len())
+            };
+            match self.node(node, &key) {
+                // Adjacent text is merged, as React would render it
+                Some(Value::String(text)) => match children.last_mut() {
+                    Some(Value::String(last)) => last.push_str(&text),
+                    _ => children.push(Value::String(text)),
+                },
+                Some(child) => children.push(child),
+                None => {}
+            }
+        }
+        children
+    }
+
+    fn inline(&self, source: &str, key: &str) -> Vec<Value> {
+        self.children(&self.nested.parse(source, true, true, false), key)
+    }
+
+    fn node(&self, node: &ASTNode, key: &str) -> Option<Value> {
+        let content = node_value(node, "content");
+        let simple = |tag: &str| element(tag, &[], self.inline(content, key), key);
+        let result = match node_value(node, "type") {
+            "text" => json!(content),
+            "heading" => element(
+                &format!("h{}", node_value(node, "level")),
+                &[("id", node_value(node, "id"))],
+                self.inline(content, key),
+                key,
+            ),
+            "paragraph" => element(
+                "div",
+                &[("className", "paragraph")],
+                self.inline(content, key),
+                key,
+            ),
+            "codeBlock" => {
+                let lang = node_value(node, "lang");
+                let class_name = if lang.is_empty() {
+                    String::new()
+                } else {
+                    format!("markdown-code-{}", lang)
+                };
+                let code = element(
+                    "code",
+                    &[("className", &class_name)],
+                    vec![json!(content)],
+                    &format!("{}.0", key),
+                );
+                element("pre", &[], vec![code], key)
+            }
+            "blockQuote" => element(
+                "blockquote",
+                &[],
+                self.children(&self.nested.parse(content, false, true, false), key),
+                key,
+            ),
+            "list" => {
+                let checked = list_checked(node);
+                let items: Vec<Value> = list_items(node)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let item_key = format!("{}.{}", key, i);
+                        let ast = if item.contains("\n\n") {
+                            self.nested
+                                .parse(&format!("{}\n\n", item), false, true, false)
+                        } else {
+                            self.nested.parse(item, true, true, false)
+                        };
+                        let mut children = self.children(&ast, &item_key);
+                        let Some(checked) = checked[i] else {
+                            return element("li", &[], children, &item_key);
+                        };
+                        // Keyed apart from the content, so its keys don't shift
+                        let mut checkbox = element(
+                            "input",
+                            &[("type", "checkbox")],
+                            vec![],
+                            &format!("{}.checkbox", item_key),
+                        );
+                        checkbox["props"]["checked"] = json!(checked);
+                        checkbox["props"]["disabled"] = json!(true);
+                        children.insert(0, checkbox);
+                        element(
+                            "li",
+                            &[("className", "task-list-item")],
+                            children,
+                            &item_key,
+                        )
+                    })
+                    .collect();
+                if node_value(node, "ordered") == "true" {
+                    element("ol", &[("start", node_value(node, "start"))], items, key)
+                } else {
+                    element("ul", &[], items, key)
+                }
+            }
+            "table" => {
+                let align = table_align(node);
+                let row = |cells: &[String], tag: &str, row_key: &str| {
+                    let cells: Vec<Value> = cells
+                        .iter()
+                        .enumerate()
+                        .map(|(column, cell)| {
+                            let cell_key = format!("{}.{}", row_key, column);
+                            let mut cell =
+                                element(tag, &[], self.inline(cell, &cell_key), &cell_key);
+                            // React takes styles as an object
+                            match align.get(column).map(String::as_str) {
+                                Some("") | None => {}
+                                Some(align) => {
+                                    cell["props"]["style"] = json!({ "textAlign": align })
+                                }
+                            }
+                            cell
+                        })
+                        .collect();
+                    element("tr", &[], cells, row_key)
+                };
+                let head_key = format!("{}.0", key);
+                let body_key = format!("{}.1", key);
+                let header_row = row(&table_header(node), "th", &format!("{}.0", head_key));
+                let body_rows: Vec<Value> = table_cells(node)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cells)| row(cells, "td", &format!("{}.{}", body_key, i)))
+                    .collect();
+                element(
+                    "table",
+                    &[],
+                    vec![
+                        element("thead", &[], vec![header_row], &head_key),
+                        element("tbody", &[], body_rows, &body_key),
+                    ],
+                    key,
+                )
+            }
+            "footnoteRef" => {
+                let label = node_value(node, "label");
+                let Some(footnote) = self.footnotes.get(label) else {
+                    return Some(json!(reference_source(node)));
+                };
+                // Links can't be nested, so references in link text only show the number
+                if self.in_link.get() {
+                    return Some(element(
+                        "sup",
+                        &[],
+                        vec![json!(footnote.number.to_string())],
+                        key,
+                    ));
+                }
+                let mut references = self.references.borrow_mut();
+                let reference = references.entry(label.to_string()).or_insert(0);
+                *reference += 1;
+                let link = element(
+                    "a",
+                    &[
+                        ("href", &format!("#{}", footnote_id(footnote.number))),
+                        ("id", &reference_id(footnote.number, *reference)),
+                        ("className", "footnote-ref"),
+                    ],
+                    vec![json!(footnote.number.to_string())],
+                    &format!("{}.0", key),
+                );
+                element("sup", &[], vec![link], key)
+            }
+            "footnotes" => {
+                let items: Vec<Value> = footnote_items(node)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, footnote)| {
+                        let item_key = format!("{}.0.{}", key, i);
+                        let ast = self.nested.parse(
+                            &format!("{}\n\n", footnote.content),
+                            false,
+                            true,
+                            false,
+                        );
+                        let mut children = self.children(&ast, &item_key);
+                        for reference in 1..=footnote.references {
+                            let backref_key = format!("{}.{}", item_key, children.len());
+                            children.push(element(
+                                "a",
+                                &[
+                                    (
+                                        "href",
+                                        &format!("#{}", reference_id(footnote.number, reference)),
+                                    ),
+                                    ("className", "footnote-backref"),
+                                    ("aria-label", "Back to reference"),
+                                ],
+                                vec![json!("↩")],
+                                &backref_key,
+                            ));
+                        }
+                        element(
+                            "li",
+                            &[("id", &footnote_id(footnote.number))],
+                            children,
+                            &item_key,
+                        )
+                    })
+                    .collect();
+                let list = element("ol", &[], items, &format!("{}.0", key));
+                element("section", &[("className", "footnotes")], vec![list], key)
+            }
+            "hr" => element("hr", &[], vec![], key),
+            "br" => element("br", &[], vec![], key),
+            "link" => {
+                let href = sanitize_url(node_value(node, "target")).unwrap_or_default();
+                let in_link = self.in_link.replace(true);
+                let children = self.children(&self.nested.parse(content, true, true, true), key);
+                self.in_link.set(in_link);
+                element(
+                    "a",
+                    &[("href", &href), ("title", node_value(node, "title"))],
+                    children,
+                    key,
+                )
+            }
+            "image" => {
+                let src = sanitize_url(node_value(node, "target")).unwrap_or_default();
+                element(
+                    "img",
+                    &[
+                        ("src", &src),
+                        ("alt", node_value(node, "alt")),
+                        ("title", node_value(node, "title")),
+                    ],
+                    vec![],
+                    key,
+                )
+            }
+            "em" => simple("em"),
+            "strong" => simple("strong"),
+            "u" => simple("u"),
+            "del" => simple("del"),
+            "spoiler" => element(
+                "span",
+                &[("className", "spoiler")],
+                self.inline(content, key),
+                key,
+            ),
+            "inlineCode" => element("code", &[], vec![json!(content)], key),
+            "emoji" => {
+                let src = sanitize_url(&emoji_url(node, &self.state.options.get::<EmojiOptions>()));
+                let name = emoji_text(node);
+                element(
+                    "img",
+                    &[
+                        ("className", "emoji"),
+                        ("src", &src.unwrap_or_default()),
+                        ("alt", &name),
+                        ("title", &name),
+                    ],
+                    vec![],
+                    key,
+                )
+            }
+            "mention" => element(
+                "span",
+                &[
+                    (
+                        "className",
+                        &format!("mention mention-{}", node_value(node, "kind")),
+                    ),
+                    ("data-id", node_value(node, "id")),
+                ],
+                vec![json!(mention_text(node, self.state.mentions.as_deref()))],
+                key,
+            ),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+/// Converts `ast` to a JSON tree of `{tag, props, children, key}` elements, like
+/// upstream's `reactFor`. Text children are plain strings, props use React
+/// names (`className`), and keys are derived from each node's position in the
+/// tree, so the same input always gets the same keys.
+pub fn to_vdom(ast: &[ASTNode], state: Option<OutputState>) -> Value {
+    let footnotes = ast
+        .iter()
+        .filter(|node| node_value(node, "type") == "footnotes")
+        .flat_map(footnote_items)
+        .map(|footnote| (footnote.label.clone(), footnote))
+        .collect();
+    let writer = VdomWriter {
+        nested: NestedParser::new(create_default_rules()),
+        state: state.unwrap_or_default(),
+        footnotes,
+        references: RefCell::new(HashMap::new()),
+        in_link: Cell::new(false),
+    };
+    Value::Array(writer.children(ast, ""))
+}